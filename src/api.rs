@@ -1,2 +1,3 @@
 pub mod chagpt;
 pub mod eth;
+pub mod metrics;
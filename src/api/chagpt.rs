@@ -1,17 +1,37 @@
 use actix_http::ws::Codec;
 use actix_web::{get, web, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
+use serde::Deserialize;
 
 use crate::libs::{
     chagpt::admin::ChaGPTAdminActor, chagpt::chagpt::ChaGPTActor, chagpt::emitter::DanmakuEmitter,
-    ws::WsActor,
+    chagpt::identity::Identity,
+    request,
+    ws::{Policy, WsActor},
 };
 
+#[derive(Deserialize)]
+pub struct ChaGPTQuery {
+    token: Option<String>,
+}
+
 #[get("/chagpt")]
-pub async fn chagpt(req: HttpRequest, stream: web::Payload) -> actix_web::Result<HttpResponse> {
+pub async fn chagpt(
+    req: HttpRequest,
+    stream: web::Payload,
+    web::Query(query): web::Query<ChaGPTQuery>,
+) -> actix_web::Result<HttpResponse> {
+    // a missing or forged token just gets a fresh identity
+    let identity = query
+        .token
+        .as_deref()
+        .and_then(Identity::from_token)
+        .unwrap_or_else(Identity::issue);
+    let ip = request::client_ip_key(&req);
+
     let mut res = ws::handshake(&req)?;
     Ok(res.streaming(ws::WebsocketContext::with_codec(
-        WsActor::new(ChaGPTActor, true),
+        WsActor::new(ChaGPTActor::new(identity, ip), true, Policy::DropOldest),
         stream,
         Codec::new().max_size(0x7fff_ffff),
     )))
@@ -22,9 +42,11 @@ pub async fn chagpt_admin(
     req: HttpRequest,
     stream: web::Payload,
 ) -> actix_web::Result<HttpResponse> {
+    let ip = request::client_ip(&req);
+
     let mut res = ws::handshake(&req)?;
     Ok(res.streaming(ws::WebsocketContext::with_codec(
-        WsActor::new(ChaGPTAdminActor::default(), true),
+        WsActor::new(ChaGPTAdminActor::new(ip), true, Policy::Disconnect),
         stream,
         Codec::new().max_size(0x7fff_ffff),
     )))
@@ -34,7 +56,7 @@ pub async fn chagpt_admin(
 pub async fn emitter(req: HttpRequest, stream: web::Payload) -> actix_web::Result<HttpResponse> {
     let mut res = ws::handshake(&req)?;
     Ok(res.streaming(ws::WebsocketContext::with_codec(
-        WsActor::new(DanmakuEmitter, true),
+        WsActor::new(DanmakuEmitter::default(), true, Policy::Disconnect),
         stream,
         Codec::new().max_size(0x7fff_ffff),
     )))
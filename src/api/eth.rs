@@ -1,16 +1,18 @@
-use std::{sync::atomic::Ordering, time::SystemTime};
+use std::sync::atomic::Ordering;
 
 use actix_web::web::{self, Bytes};
-use bytes::Buf;
 use bytestring::ByteString;
 use serde::Deserialize;
 
 use crate::libs::{
-    chagpt::{admin::CURRENT_ADMIN, Emit},
+    chagpt::{admin, audit, Emit},
     constants::{BYTES_FALSE, BYTES_NULL, BYTES_TRUE, LOTTERY_SECRET},
     eth::{self, FETCHER_WORK},
 };
 
+/// The endpoints here only know the lottery secret, so that is who the audit log names.
+const LOTTERY_ADMIN: &str = "lottery-secret";
+
 #[derive(Deserialize)]
 pub struct BlockRequest {
     secret: String,
@@ -23,36 +25,19 @@ pub async fn block(web::Json(req): web::Json<BlockRequest>) -> Bytes {
         return BYTES_NULL;
     }
 
-    let Some(block) = eth::fetch() else {
+    let Some(json) = eth::draw() else {
         return BYTES_NULL;
     };
 
-    let bt = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(block.time);
-    let nt = SystemTime::now();
-    let now = unsafe {
-        nt.duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap_unchecked()
-            .as_millis()
-    };
-
-    let payload = Emit(ByteString::from(format!(
-        r#"4{{"type":"lottery","block":{},"hash":"{}","blockTime":{},"now":{now}}}"#,
-        block.height,
-        block.hash,
-        block.time * 1000,
-    )));
-
-    tracing::info!(target: "eth-request", "Block {} (blockTime: {bt:?}, now: {nt:?}) is taken", block.height);
-
-    if let Some(ref addr) = *CURRENT_ADMIN.read() {
-        if let Err(e) = addr.do_send(payload.clone()) {
-            tracing::error!(target: "danmaku-to-admin", err = ?e);
-        }
-    }
+    audit::record(
+        LOTTERY_ADMIN,
+        "lottery",
+        serde_json::from_str(&json).unwrap_or_default(),
+        Ok(()),
+    );
+    admin::notify(Emit(ByteString::from(format!("4{json}"))));
 
-    let mut payload = payload.0.into_bytes();
-    payload.advance(1);
-    payload
+    Bytes::from(json)
 }
 
 #[derive(Deserialize)]
@@ -69,6 +54,12 @@ pub async fn fetch(web::Json(req): web::Json<FetchRequest>) -> Bytes {
     }
 
     let old = FETCHER_WORK.swap(new, Ordering::SeqCst);
+    audit::record(
+        LOTTERY_ADMIN,
+        "fetcher",
+        serde_json::json!({ "old": old, "new": new }),
+        Ok(()),
+    );
 
     if old {
         BYTES_TRUE
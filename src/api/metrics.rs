@@ -0,0 +1,22 @@
+use actix_web::{get, http::header, HttpRequest, HttpResponse};
+
+use crate::libs::{config::CONFIG, metrics as counters, token};
+
+#[get("/metrics")]
+pub async fn metrics(req: HttpRequest) -> HttpResponse {
+    let Some(ref expected) = CONFIG.metrics_token else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let authorized = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| token::matches(given.trim(), expected));
+    if !authorized {
+        return HttpResponse::Unauthorized().finish();
+    }
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(counters::render())
+}
@@ -1,9 +1,12 @@
 pub mod chagpt;
+pub mod config;
 pub mod constants;
 pub mod db;
 pub mod eth;
 pub mod logger;
+pub mod metrics;
 pub mod request;
 pub mod response;
+pub mod token;
 pub mod util;
 pub mod ws;
@@ -1,21 +1,52 @@
-use bytestring::ByteString;
+pub use super::ws::Emit;
 
+use super::{constants::OUTBOX_SWEEP_INTERVAL, ws};
+
+pub mod ack;
 pub mod admin;
+pub mod announcement;
+pub mod audit;
+pub mod auth;
+pub mod batcher;
 pub mod chagpt;
+pub mod content;
 pub mod danmaku;
+pub mod display;
 pub mod emitter;
+pub mod error;
+pub mod featured;
+pub mod identity;
+pub mod mode;
+pub mod moderation;
 pub mod repertoire;
+pub mod scheduler;
+pub mod spool;
+pub mod stats;
 
 pub async fn init() {
+    // before the spool is read, whose held danmakus go on top of the stored ones
+    if let Err(e) = moderation::init().await {
+        tracing::warn!(target: "ChaGPT-init", "failed to init moderation: {e:?}");
+    }
+    batcher::init().await;
     if let Err(e) = repertoire::init().await {
         tracing::warn!(target: "ChaGPT-init", "failed to init repertoire: {e:?}");
     }
+    if let Err(e) = display::init().await {
+        tracing::warn!(target: "ChaGPT-init", "failed to init display settings: {e:?}");
+    }
+    if let Err(e) = mode::init().await {
+        tracing::warn!(target: "ChaGPT-init", "failed to init mode: {e:?}");
+    }
 }
 
-#[derive(Clone)]
-#[repr(transparent)]
-pub struct Emit(pub ByteString);
+pub async fn sweeper() {
+    loop {
+        tokio::time::sleep(OUTBOX_SWEEP_INTERVAL).await;
 
-impl actix::Message for Emit {
-    type Result = ();
+        ws::prune(&*chagpt::ACTORS);
+        ws::prune(&*admin::ADMINS);
+        emitter::prune();
+        mode::prune();
+    }
 }
@@ -0,0 +1,143 @@
+use std::{collections::VecDeque, sync::LazyLock, time::Instant};
+
+use ahash::HashMap;
+use bytestring::ByteString;
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::libs::constants::{NONCE_MAX_ENTRIES, NONCE_WINDOW};
+
+use super::{error::ErrorCode, identity::Identity, Emit};
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "ack")]
+struct Ack<'a> {
+    nonce: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<ErrorCode>,
+    #[serde(skip_serializing_if = "core::ops::Not::not")]
+    pending: bool,
+}
+
+fn payload(nonce: Option<&str>, id: Option<u32>, reason: Option<ErrorCode>, pending: bool) -> Emit {
+    let json = serde_json::to_string(&Ack {
+        nonce,
+        id,
+        reason,
+        pending,
+    })
+    .unwrap_or_default();
+    Emit(ByteString::from(format!("4{json}")))
+}
+
+#[inline]
+pub fn accepted(nonce: Option<&str>, id: u32) -> Emit {
+    payload(nonce, Some(id), None, false)
+}
+
+#[inline]
+pub fn rejected(nonce: Option<&str>, reason: ErrorCode) -> Emit {
+    payload(nonce, None, Some(reason), false)
+}
+
+#[inline]
+pub fn pending(nonce: &str) -> Emit {
+    payload(Some(nonce), None, None, true)
+}
+
+pub enum Seen {
+    New(Claim),
+    Pending,
+    Done(Emit),
+}
+
+type Key = (Identity, String);
+
+#[derive(Default)]
+struct Nonces {
+    results: HashMap<Key, (Instant, Option<Emit>)>,
+    expiry: VecDeque<(Instant, Key)>,
+}
+
+impl Nonces {
+    /// A claim released and made again has a later time and is kept.
+    fn pop(&mut self) {
+        if let Some((time, key)) = self.expiry.pop_front()
+            && self.results.get(&key).is_some_and(|(claimed, _)| *claimed == time)
+        {
+            self.results.remove(&key);
+        }
+    }
+}
+
+static NONCES: LazyLock<Mutex<Nonces>> = LazyLock::new(|| Mutex::new(Nonces::default()));
+
+pub fn begin(identity: Identity, nonce: &str) -> Seen {
+    let now = Instant::now();
+    let mut guard = NONCES.lock();
+    let nonces = &mut *guard;
+
+    while let Some((time, _)) = nonces.expiry.front()
+        && (now.duration_since(*time) > NONCE_WINDOW || nonces.expiry.len() >= NONCE_MAX_ENTRIES)
+    {
+        nonces.pop();
+    }
+
+    let key = (identity, nonce.to_owned());
+    match nonces.results.get(&key) {
+        Some((_, Some(ack))) => Seen::Done(ack.clone()),
+        Some((_, None)) => Seen::Pending,
+        None => {
+            nonces.results.insert(key.clone(), (now, None));
+            nonces.expiry.push_back((now, key.clone()));
+            Seen::New(Claim(key))
+        }
+    }
+}
+
+/// Dropping it without an ack, whether the attempt was rejected or dropped with its socket,
+/// forgets the nonce so that a retry is tried again. Its place in the expiry queue is skipped
+/// when it comes up.
+pub struct Claim(Key);
+
+impl Claim {
+    pub fn finish(self, ack: &Emit) {
+        if let Some((_, result)) = NONCES.lock().results.get_mut(&self.0) {
+            *result = Some(ack.clone());
+        }
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        let mut guard = NONCES.lock();
+        if guard.results.get(&self.0).is_some_and(|(_, result)| result.is_none()) {
+            guard.results.remove(&self.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{accepted, begin, Seen};
+    use crate::libs::chagpt::identity::Identity;
+
+    #[test]
+    fn dropped_claim() {
+        let identity = Identity(0x0ac4);
+
+        let Seen::New(claim) = begin(identity, "dropped") else {
+            panic!("a new nonce is claimed");
+        };
+        assert!(matches!(begin(identity, "dropped"), Seen::Pending));
+        drop(claim);
+        let Seen::New(claim) = begin(identity, "dropped") else {
+            panic!("a dropped claim is forgotten");
+        };
+
+        claim.finish(&accepted(Some("dropped"), 1));
+        assert!(matches!(begin(identity, "dropped"), Seen::Done(_)));
+    }
+}
@@ -1,30 +1,113 @@
-use actix::{fut::wrap_future, ActorFutureExt, Addr, AsyncContext, Handler};
+use std::{net::IpAddr, sync::LazyLock, time::SystemTime};
+
+use actix::{fut::wrap_future, ActorFutureExt, AsyncContext};
 use actix_web_actors::ws;
+use ahash::HashMap;
 use bytestring::ByteString;
 use parking_lot::RwLock;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 use crate::libs::{
-    constants::ADMIN_SECRET,
-    ws::{AppWsActor, WsActor},
+    constants::{AUDIT_PAGE_SIZE, FEATURED_DURATION},
+    db::BB8Error,
+    eth,
+    util::millis,
+    ws::{AppWsActor, Peer, WsActor},
 };
 
 use super::{
-    chagpt::ACTORS,
-    emitter::CURRENT_EMITTER,
+    announcement::{self, Style},
+    audit,
+    auth::{self, Admin},
+    batcher,
+    chagpt,
+    danmaku::Danmaku,
+    display::{self, Settings},
+    emitter::{self, Filter},
+    error::ErrorCode,
+    featured, mode,
+    moderation::{self, BanKind, Target},
     repertoire::{self, Program, Repertoire, REPERTOIRE},
+    scheduler::{Item, Priority},
+    spool, stats,
     Emit,
 };
 
-#[derive(Default)]
+/// What an admin session may do; each role includes the ones before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    Viewer,
+    Moderator,
+    Director,
+}
+
+impl Role {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Viewer => "viewer",
+            Self::Moderator => "moderator",
+            Self::Director => "director",
+        }
+    }
+
+    pub fn from_db(role: &str) -> Self {
+        match role {
+            "director" => Self::Director,
+            "moderator" => Self::Moderator,
+            _ => Self::Viewer,
+        }
+    }
+}
+
 pub struct ChaGPTAdminActor {
-    is_login: bool,
+    ip: Option<IpAddr>,
+    admin: Option<Admin>,
+    peer: Option<Peer<Self>>,
 }
 pub type ChaGPTAdminWsActor = WsActor<ChaGPTAdminActor>;
 pub type ChaGPTAdminContext = ws::WebsocketContext<ChaGPTAdminWsActor>;
-type MaybeAddress = Option<Addr<ChaGPTAdminWsActor>>;
 
-pub static CURRENT_ADMIN: RwLock<MaybeAddress> = RwLock::new(None);
+type Map = HashMap<Peer<ChaGPTAdminActor>, Role>;
+
+pub static ADMINS: LazyLock<RwLock<Map>> = LazyLock::new(|| RwLock::new(Map::default()));
+
+pub fn notify(payload: Emit) {
+    for admin in ADMINS.read().keys() {
+        if let Err(e) = admin.send_critical(payload.clone()) {
+            tracing::error!(target: "to-admin", err = ?e);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Login {
+    Password { name: String, password: String },
+    Session { session: String },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "login")]
+struct LoginEvent<'a> {
+    name: &'a str,
+    role: Role,
+    session: &'a str,
+    expires: u128,
+}
+
+#[derive(Deserialize)]
+struct Checked {
+    id: Option<u32>,
+    content: String,
+    color: u32,
+    screens: Option<Vec<String>>,
+    #[serde(default)]
+    priority: Priority,
+    #[serde(default)]
+    featured: bool,
+}
 
 #[derive(Deserialize)]
 #[serde(tag = "type")]
@@ -35,90 +118,534 @@ enum Message {
         current: u32,
     },
     #[serde(rename = "danmaku-checked")]
-    DanChk { content: String, color: u32 },
+    DanChk(Checked),
+    #[serde(rename = "danmaku-delete")]
+    DanDel { id: u32 },
+    #[serde(rename = "announce")]
+    Announce {
+        content: String,
+        #[serde(default)]
+        style: Style,
+        pin: Option<u64>,
+        #[serde(default)]
+        emitter: bool,
+    },
+    #[serde(rename = "announcement-unpin")]
+    Unpin { id: u32 },
+    #[serde(rename = "ban")]
+    Ban {
+        #[serde(flatten)]
+        target: Target,
+        kind: BanKind,
+        #[serde(default)]
+        reason: String,
+        duration: Option<u64>,
+    },
+    #[serde(rename = "ban-lift")]
+    BanLift { id: u32 },
+    #[serde(rename = "ban-list")]
+    BanList,
+    #[serde(rename = "lottery")]
+    Lottery,
+    #[serde(rename = "mode")]
+    Mode {
+        #[serde(flatten)]
+        update: mode::Update,
+    },
+    #[serde(rename = "featured-list")]
+    FeaturedList { program: Option<u32> },
+    #[serde(rename = "emitter-filter")]
+    EmitterFilter { name: String, filter: Filter },
+    #[serde(rename = "emitter-list")]
+    EmitterList,
+    #[serde(rename = "display-settings")]
+    DisplaySettings {
+        screen: Option<String>,
+        settings: Settings,
+    },
+    #[serde(rename = "audit-log")]
+    AuditLog {
+        before: Option<u32>,
+        limit: Option<u32>,
+    },
 }
 
-impl AppWsActor for ChaGPTAdminActor {
-    fn started(&mut self, _ctx: &mut ChaGPTAdminContext, _hash: u64) {
-        // TODO
+impl Message {
+    const fn role(&self) -> Role {
+        match self {
+            Self::BanList
+            | Self::FeaturedList { .. }
+            | Self::EmitterList
+            | Self::AuditLog { .. } => Role::Viewer,
+            Self::DanChk(_)
+            | Self::DanDel { .. }
+            | Self::Ban { .. }
+            | Self::BanLift { .. }
+            | Self::Mode { .. } => Role::Moderator,
+            Self::RepUp { .. }
+            | Self::Announce { .. }
+            | Self::Unpin { .. }
+            | Self::EmitterFilter { .. }
+            | Self::DisplaySettings { .. }
+            | Self::Lottery => Role::Director,
+        }
     }
+}
 
-    fn stopped(&mut self, ctx: &mut ChaGPTAdminContext, _hash: u64) {
-        let mut guard = CURRENT_ADMIN.write();
-        if Some(ctx.address()) == *guard {
-            *guard = None;
+fn on_bans_changed(
+    res: Result<(), BB8Error>,
+    ctx: &mut ChaGPTAdminContext,
+) -> Result<(), ErrorCode> {
+    match res {
+        Ok(()) => {
+            notify(Emit(ByteString::from(moderation::payload())));
+            Ok(())
+        }
+        Err(e) => {
+            tracing::warn!(target: "ChaGPT-admin", "failed to update bans: {e:?}");
+            ctx.text(ErrorCode::StorageFailure.event().0);
+            Err(ErrorCode::StorageFailure)
         }
     }
+}
 
-    fn handle_text(&mut self, ctx: &mut ChaGPTAdminContext, text: &str) {
-        if !self.is_login {
-            if text.trim() == ADMIN_SECRET {
-                tracing::debug!(target: "ChaGPT-admin", "admin connected");
-                self.is_login = true;
-                *CURRENT_ADMIN.write() = Some(ctx.address());
-                if let Some(r) = REPERTOIRE.read().as_ref()
-                    && let Ok(programs) = serde_json::to_string(&r.programs)
-                {
-                    let payload = format!(
-                        r#"4{{"type":"repertoire","programs":{programs},"current":{}}}"#,
-                        r.current,
-                    );
-                    ctx.text(payload);
-                }
+struct Audited {
+    admin: String,
+    value: Value,
+}
+
+impl Audited {
+    fn record(self, outcome: Result<(), ErrorCode>) {
+        let action = match self.value.get("type") {
+            Some(Value::String(action)) => action.clone(),
+            _ => String::new(),
+        };
+        audit::record(&self.admin, &action, self.value, outcome);
+    }
+}
+
+impl ChaGPTAdminActor {
+    pub const fn new(ip: Option<IpAddr>) -> Self {
+        Self {
+            ip,
+            admin: None,
+            peer: None,
+        }
+    }
+
+    fn logged_in(&mut self, ctx: &mut ChaGPTAdminContext, res: Result<Admin, ErrorCode>) {
+        let admin = match res {
+            Ok(admin) => admin,
+            Err(code) => {
+                tracing::info!(target: "ChaGPT-admin", "login from {:?} failed: {code:?}", self.ip);
+                ctx.text(code.event().0);
+                return;
             }
+        };
+        let Some(peer) = self.peer.clone() else {
             return;
+        };
+
+        tracing::info!(target: "ChaGPT-admin", "{} connected as {:?}", admin.name, admin.role);
+        let (session, expires) = auth::session(&admin);
+        if let Ok(json) = serde_json::to_string(&LoginEvent {
+            name: &admin.name,
+            role: admin.role,
+            session: &session,
+            expires,
+        }) {
+            ctx.text(format!("4{json}"));
         }
+        ADMINS.write().insert(peer, admin.role);
+        self.admin = Some(admin);
+
+        if let Some(r) = REPERTOIRE.read().as_ref()
+            && let Ok(programs) = serde_json::to_string(&r.programs)
+        {
+            let payload = format!(
+                r#"4{{"type":"repertoire","programs":{programs},"current":{}}}"#,
+                r.current,
+            );
+            ctx.text(payload);
+        }
+        ctx.text(mode::payload());
+        if spool::backlog() > 0 {
+            spool::report();
+        }
+        emitter::report();
+    }
 
-        let Ok(msg) = serde_json::from_str::<Message>(text) else {
+    fn repertoire_update(ctx: &mut ChaGPTAdminContext, audited: Audited, data: Repertoire) {
+        ctx.wait(wrap_future(repertoire::update(data)).map(|payload, _actor, _ctx| {
+            let payload = Emit(ByteString::from(payload));
+            chagpt::broadcast(&payload, true);
+            notify(payload);
+            audited.record(Ok(()));
+        }));
+    }
+
+    fn danmaku_checked(ctx: &mut ChaGPTAdminContext, audited: Audited, checked: Checked) {
+        let Checked {
+            id,
+            content,
+            color,
+            screens,
+            priority,
+            featured,
+        } = checked;
+        // a featured danmaku is listed by id afterwards
+        if featured && id.is_none() {
+            ctx.text(ErrorCode::Malformed.event().0);
+            audited.record(Err(ErrorCode::Malformed));
+            return;
+        }
+        let Ok(content) = serde_json::to_string(&content) else {
             return;
         };
-        match msg {
-            Message::RepUp { programs, current } => {
-                ctx.wait(
-                    wrap_future(repertoire::update(Repertoire { programs, current })).map(
-                        |res, _actor, _ctx| {
-                            match res {
-                                Err(e) => tracing::warn!(target: "ChaGPT-admin", "failed to update repertoire: {e:?}"),
-                                Ok(payload) => {
-                                    let payload = Emit(ByteString::from(payload));
-                                    let guard = ACTORS.read();
-                                    for actor in &*guard {
-                                        if let Err(e) = actor.do_send(payload.clone()) {
-                                            tracing::error!(target: "repertoire-update-to-client", err = ?e);
-                                        }
-                                    }
-                                }
-                            }
-                        },
-                    ),
-                );
+        // screens echo the id back once they have displayed it
+        let mut fields = match id {
+            Some(id) => format!(r#""id":{id},"content":{content},"color":{color}"#),
+            None => format!(r#""content":{content},"color":{color}"#),
+        };
+        if featured {
+            fields = format!(
+                r#"{fields},"featured":true,"style":"highlight","duration":{}"#,
+                FEATURED_DURATION.as_millis()
+            );
+        }
+        let payload = Emit(ByteString::from(format!("4{{{fields}}}")));
+        tracing::debug!("emit {:?}", payload.0);
+        let checked = Emit(ByteString::from(format!(
+            r#"4{{"type":"danmaku-checked",{fields}}}"#
+        )));
+        // only the sender has seen a held danmaku so far, so approving it shows it to the
+        // audience as well
+        let audience = move |id: u32, time: SystemTime| {
+            let timestamp = millis(time);
+            Emit(ByteString::from(format!(
+                r#"4{{"type":"danmaku","id":{id},"content":{content},"time":{timestamp},"color":{color}}}"#
+            )))
+        };
+        let item = Item {
+            id,
+            payload,
+            priority: if featured { Priority::High } else { priority },
+        };
+        let Some(id) = id.filter(|_| featured) else {
+            emitter::approve(item, screens.as_deref());
+            notify(checked);
+            if let Some(id) = id
+                && let Some(time) = moderation::release(id)
+            {
+                chagpt::broadcast(&audience(id, time), false);
+            }
+            audited.record(Ok(()));
+            return;
+        };
+
+        // nothing reaches the screens or the audience until it is stored as featured
+        let program = REPERTOIRE.read().as_ref().map(|r| r.current);
+        let feature = async move {
+            // a recent danmaku may still be queued, or spooled while the database is down
+            if !batcher::written(id).await {
+                return None;
             }
-            Message::DanChk { content, color } => {
-                let Ok(content) = serde_json::to_string(&content) else {
+            Some(featured::feature(id, program).await)
+        };
+        ctx.wait(wrap_future(feature).map(
+            move |res, _actor, ctx: &mut ChaGPTAdminContext| {
+                match res {
+                    Some(Ok(true)) => {}
+                    Some(Ok(false)) => {
+                        ctx.text(ErrorCode::NotFound.event().0);
+                        audited.record(Err(ErrorCode::NotFound));
+                        return;
+                    }
+                    res => {
+                        if let Some(Err(e)) = res {
+                            tracing::warn!(target: "ChaGPT-admin", "failed to store featured danmaku: {e:?}");
+                        }
+                        ctx.text(ErrorCode::StorageFailure.event().0);
+                        audited.record(Err(ErrorCode::StorageFailure));
+                        return;
+                    }
+                }
+                emitter::route(item, screens.as_deref());
+                notify(checked);
+                if let Some(time) = moderation::release(id) {
+                    chagpt::broadcast(&audience(id, time), false);
+                }
+
+                let payload = Emit(ByteString::from(format!(
+                    r#"4{{"type":"danmaku-featured","id":{id}}}"#
+                )));
+                chagpt::broadcast(&payload, true);
+                notify(payload);
+                audited.record(Ok(()));
+            },
+        ));
+    }
+
+    fn danmaku_delete(ctx: &mut ChaGPTAdminContext, audited: Audited, id: u32) {
+        ctx.wait(wrap_future(Danmaku::retract(id)).map(
+            move |found, _actor, ctx: &mut ChaGPTAdminContext| {
+                if !found {
+                    ctx.text(ErrorCode::NotFound.event().0);
+                    audited.record(Err(ErrorCode::NotFound));
                     return;
-                };
+                }
+                tracing::info!(target: "ChaGPT-admin", "danmaku {id} retracted");
+                moderation::release(id);
                 let payload = Emit(ByteString::from(format!(
-                    r#"4{{"content":{content},"color":{color}}}"#
+                    r#"4{{"type":"danmaku-delete","id":{id}}}"#
                 )));
-                tracing::debug!("emit {:?}", payload.0);
-                if let Some(ref addr) = *CURRENT_EMITTER.read() {
-                    if let Err(e) = addr.do_send(payload) {
-                        tracing::error!(target: "danmaku-to-emitter", err = ?e);
-                    }
+                chagpt::broadcast(&payload, true);
+                emitter::retract(id, payload.clone());
+                notify(payload);
+                audited.record(Ok(()));
+            },
+        ));
+    }
+
+    fn ban(
+        ctx: &mut ChaGPTAdminContext,
+        audited: Audited,
+        target: Target,
+        kind: BanKind,
+        reason: String,
+        duration: Option<u64>,
+    ) {
+        let created = SystemTime::now();
+        let until = match moderation::until(created, duration) {
+            Ok(until) => until,
+            Err(code) => {
+                ctx.text(code.event().0);
+                audited.record(Err(code));
+                return;
+            }
+        };
+        let fut = moderation::add(target.clone(), kind, reason, created, until);
+        ctx.wait(wrap_future(fut).map(move |res, _actor, ctx| {
+            if res.is_ok() && kind == BanKind::Ban {
+                chagpt::disconnect(&target);
+            }
+            audited.record(on_bans_changed(res, ctx));
+        }));
+    }
+
+    fn ban_lift(ctx: &mut ChaGPTAdminContext, audited: Audited, id: u32) {
+        ctx.wait(wrap_future(moderation::lift(id)).map(
+            |res, _actor, ctx: &mut ChaGPTAdminContext| {
+                if matches!(res, Ok(false)) {
+                    ctx.text(ErrorCode::NotFound.event().0);
+                    audited.record(Err(ErrorCode::NotFound));
+                    return;
                 }
+                audited.record(on_bans_changed(res.map(|_| ()), ctx));
+            },
+        ));
+    }
+
+    fn lottery(ctx: &mut ChaGPTAdminContext, audited: Audited) {
+        let Some(json) = eth::draw() else {
+            tracing::warn!(target: "ChaGPT-admin", "no block available for the lottery");
+            ctx.text(ErrorCode::NotFound.event().0);
+            audited.record(Err(ErrorCode::NotFound));
+            return;
+        };
+        audit::record(
+            &audited.admin,
+            "lottery",
+            serde_json::from_str(&json).unwrap_or_default(),
+            Ok(()),
+        );
+        notify(Emit(ByteString::from(format!("4{json}"))));
+    }
+
+    fn mode(ctx: &mut ChaGPTAdminContext, audited: Audited, update: mode::Update) {
+        if !update.is_valid() {
+            ctx.text(ErrorCode::Malformed.event().0);
+            audited.record(Err(ErrorCode::Malformed));
+            return;
+        }
+        let (old, stored) = mode::update(update);
+        let payload = Emit(ByteString::from(mode::payload()));
+        chagpt::broadcast(&payload, true);
+        notify(payload);
+        let blackout = mode::is_blackout();
+        if blackout != old.blackout {
+            tracing::warn!(target: "ChaGPT-admin", "blackout {}", if blackout { "started" } else { "lifted" });
+            emitter::blackout(blackout);
+        }
+        ctx.wait(wrap_future(stored).map(|res, _actor, ctx: &mut ChaGPTAdminContext| {
+            if let Err(e) = res {
+                tracing::warn!(target: "ChaGPT-admin", "failed to store mode: {e:?}");
+                ctx.text(ErrorCode::StorageFailure.event().0);
+                audited.record(Err(ErrorCode::StorageFailure));
+                return;
             }
+            audited.record(Ok(()));
+        }));
+    }
+
+    fn featured_list(ctx: &mut ChaGPTAdminContext, program: Option<u32>) {
+        ctx.wait(wrap_future(featured::list(program)).map(
+            |res, _actor, ctx: &mut ChaGPTAdminContext| match res {
+                Ok(featured) => ctx.text(featured::payload(&featured)),
+                Err(e) => {
+                    tracing::warn!(target: "ChaGPT-admin", "failed to read featured danmakus: {e:?}");
+                    ctx.text(ErrorCode::StorageFailure.event().0);
+                }
+            },
+        ));
+    }
+
+    fn display_settings(
+        ctx: &mut ChaGPTAdminContext,
+        audited: Audited,
+        screen: Option<String>,
+        settings: Settings,
+    ) {
+        if !settings.is_valid() || screen.as_deref() == Some(display::ALL_SCREENS) {
+            ctx.text(ErrorCode::Malformed.event().0);
+            audited.record(Err(ErrorCode::Malformed));
+            return;
         }
+        // a single screen gets its settings merged over the ones for all screens
+        let name = screen.clone();
+        let stored = display::update(screen, settings.clone());
+        let settings = name.as_deref().map_or(settings, display::get);
+        let payload = Emit(ByteString::from(display::payload(name.as_deref(), &settings)));
+        emitter::emit_to(name.as_deref(), payload.clone());
+        notify(payload);
+        ctx.wait(wrap_future(stored).map(|res, _actor, ctx: &mut ChaGPTAdminContext| {
+            if let Err(e) = res {
+                tracing::warn!(target: "ChaGPT-admin", "failed to store display settings: {e:?}");
+                ctx.text(ErrorCode::StorageFailure.event().0);
+                audited.record(Err(ErrorCode::StorageFailure));
+                return;
+            }
+            audited.record(Ok(()));
+        }));
     }
 
-    fn handle_binary(&mut self, _ctx: &mut ChaGPTAdminContext, _bin: bytes::Bytes) {
-        // TODO
+    fn audit_log(ctx: &mut ChaGPTAdminContext, before: Option<u32>, limit: Option<u32>) {
+        let limit = limit.map_or(AUDIT_PAGE_SIZE, |limit| limit.min(AUDIT_PAGE_SIZE));
+        ctx.wait(wrap_future(audit::list(before, limit)).map(
+            |res, _actor, ctx: &mut ChaGPTAdminContext| match res {
+                Ok(entries) => ctx.text(audit::payload(&entries)),
+                Err(e) => {
+                    tracing::warn!(target: "ChaGPT-admin", "failed to read audit log: {e:?}");
+                    ctx.text(ErrorCode::StorageFailure.event().0);
+                }
+            },
+        ));
     }
 }
 
-impl Handler<Emit> for ChaGPTAdminWsActor {
-    type Result = ();
+impl AppWsActor for ChaGPTAdminActor {
+    fn started(&mut self, _ctx: &mut ChaGPTAdminContext, peer: &Peer<Self>) {
+        self.peer = Some(peer.clone());
+    }
+
+    fn stopped(&mut self, _ctx: &mut ChaGPTAdminContext, peer: &Peer<Self>) {
+        ADMINS.write().remove(peer);
+    }
+
+    fn handle_text(&mut self, ctx: &mut ChaGPTAdminContext, text: &str) {
+        let Some(ref admin) = self.admin else {
+            let Ok(login) = serde_json::from_str::<Login>(text) else {
+                ctx.text(ErrorCode::Malformed.event().0);
+                return;
+            };
+            match login {
+                Login::Password { name, password } => {
+                    // `wait` holds back further messages until the login is settled
+                    let res = auth::login(name, password, self.ip);
+                    ctx.wait(wrap_future(res).map(|res, actor: &mut ChaGPTAdminWsActor, ctx| {
+                        actor.app.logged_in(ctx, res);
+                    }));
+                }
+                Login::Session { session } => {
+                    let res = auth::resume(session, self.ip);
+                    ctx.wait(wrap_future(res).map(|res, actor: &mut ChaGPTAdminWsActor, ctx| {
+                        actor.app.logged_in(ctx, res);
+                    }));
+                }
+            }
+            return;
+        };
+
+        let Ok(value) = serde_json::from_str::<Value>(text) else {
+            ctx.text(ErrorCode::Malformed.event().0);
+            return;
+        };
+        let Ok(msg) = Message::deserialize(&value) else {
+            ctx.text(ErrorCode::Malformed.event().0);
+            return;
+        };
+        if admin.role < msg.role() {
+            ctx.text(ErrorCode::Forbidden.event().0);
+            return;
+        }
+        // left unrecorded by queries, which change nothing, and by a successful lottery draw,
+        // which records the block it drew instead
+        let audited = Audited {
+            admin: admin.name.clone(),
+            value,
+        };
+        match msg {
+            Message::RepUp { programs, current } => {
+                Self::repertoire_update(ctx, audited, Repertoire { programs, current });
+            }
+            Message::DanChk(checked) => Self::danmaku_checked(ctx, audited, checked),
+            Message::DanDel { id } => Self::danmaku_delete(ctx, audited, id),
+            Message::Announce {
+                content,
+                style,
+                pin,
+                emitter,
+            } => {
+                if announcement::publish(&content, style, pin, emitter) {
+                    audited.record(Ok(()));
+                } else {
+                    ctx.text(ErrorCode::Malformed.event().0);
+                    audited.record(Err(ErrorCode::Malformed));
+                }
+            }
+            Message::Unpin { id } => {
+                if announcement::unpin(id) {
+                    audited.record(Ok(()));
+                } else {
+                    ctx.text(ErrorCode::NotFound.event().0);
+                    audited.record(Err(ErrorCode::NotFound));
+                }
+            }
+            Message::Ban {
+                target,
+                kind,
+                reason,
+                duration,
+            } => Self::ban(ctx, audited, target, kind, reason, duration),
+            Message::BanLift { id } => Self::ban_lift(ctx, audited, id),
+            Message::BanList => ctx.text(moderation::payload()),
+            Message::Lottery => Self::lottery(ctx, audited),
+            Message::Mode { update } => Self::mode(ctx, audited, update),
+            Message::FeaturedList { program } => Self::featured_list(ctx, program),
+            Message::EmitterFilter { name, filter } => {
+                emitter::set_filter(&name, filter);
+                notify(Emit(ByteString::from(emitter::payload())));
+                audited.record(Ok(()));
+            }
+            Message::EmitterList => ctx.text(emitter::payload()),
+            Message::DisplaySettings { screen, settings } => {
+                Self::display_settings(ctx, audited, screen, settings);
+            }
+            Message::AuditLog { before, limit } => Self::audit_log(ctx, before, limit),
+        }
+    }
 
-    fn handle(&mut self, msg: Emit, ctx: &mut Self::Context) -> Self::Result {
-        ctx.text(msg.0);
+    fn handle_binary(&mut self, _ctx: &mut ChaGPTAdminContext, _bin: bytes::Bytes) {
+        // TODO
     }
 }
@@ -0,0 +1,114 @@
+use core::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+use std::time::SystemTime;
+
+use bytestring::ByteString;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use crate::libs::util::millis;
+
+use super::{
+    admin, chagpt, emitter,
+    scheduler::{Item, Priority},
+    Emit,
+};
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Style {
+    #[default]
+    Info,
+    Warning,
+    Highlight,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "announcement")]
+struct Announcement<'a> {
+    id: u32,
+    content: &'a str,
+    style: Style,
+    time: u128,
+    #[serde(rename = "pinnedUntil")]
+    pinned_until: Option<u128>,
+}
+
+struct Pinned {
+    id: u32,
+    until: SystemTime,
+    payload: Emit,
+}
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+static PINNED: RwLock<Vec<Pinned>> = RwLock::new(Vec::new());
+
+pub fn publish(content: &str, style: Style, pin: Option<u64>, to_emitter: bool) -> bool {
+    let time = SystemTime::now();
+    let until = match pin {
+        Some(secs) => match time.checked_add(Duration::from_secs(secs)) {
+            Some(until) => Some(until),
+            None => return false,
+        },
+        None => None,
+    };
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+    let Ok(json) = serde_json::to_string(&Announcement {
+        id,
+        content,
+        style,
+        time: millis(time),
+        pinned_until: until.map(millis),
+    }) else {
+        return false;
+    };
+    let payload = Emit(ByteString::from(format!("4{json}")));
+
+    if let Some(until) = until {
+        PINNED.write().push(Pinned {
+            id,
+            until,
+            payload: payload.clone(),
+        });
+    }
+
+    chagpt::broadcast(&payload, true);
+    if to_emitter {
+        let item = Item {
+            id: None,
+            payload: payload.clone(),
+            priority: Priority::High,
+        };
+        emitter::route(item, None);
+    }
+    admin::notify(payload);
+    true
+}
+
+pub fn unpin(id: u32) -> bool {
+    {
+        let mut pinned = PINNED.write();
+        let len = pinned.len();
+        pinned.retain(|pinned| pinned.id != id);
+        if pinned.len() == len {
+            return false;
+        }
+    }
+
+    let payload = Emit(ByteString::from(format!(
+        r#"4{{"type":"announcement-unpin","id":{id}}}"#
+    )));
+    chagpt::broadcast(&payload, true);
+    admin::notify(payload);
+    true
+}
+
+pub fn pinned() -> Vec<Emit> {
+    let now = SystemTime::now();
+    let mut guard = PINNED.write();
+    guard.retain(|pinned| pinned.until > now);
+    guard.iter().map(|pinned| pinned.payload.clone()).collect()
+}
@@ -0,0 +1,146 @@
+use std::time::SystemTime;
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio_postgres::Row;
+
+use crate::libs::{
+    db::{get_connection, BB8Error},
+    util::serialize_millis,
+};
+
+use super::{
+    error::ErrorCode,
+    spool::{self, Entry as SpoolEntry},
+};
+
+const INSERT_AUDIT: &str = "insert into audit (admin, action, payload, outcome, created) \
+    values ($1, $2, $3, $4, $5)";
+const GET_AUDIT: &str = "select id, admin, action, payload, outcome, created from audit \
+    where id < $1 order by id desc limit $2";
+
+#[derive(Serialize)]
+pub struct Entry {
+    pub id: u32,
+    pub admin: String,
+    pub action: String,
+    pub payload: Value,
+    pub outcome: String,
+    #[serde(serialize_with = "serialize_millis")]
+    pub created: SystemTime,
+}
+
+impl Entry {
+    fn from_row(row: &Row) -> Result<Self, BB8Error> {
+        Ok(Self {
+            id: row.try_get::<_, i32>(0)? as u32,
+            admin: row.try_get(1)?,
+            action: row.try_get(2)?,
+            payload: row.try_get(3)?,
+            outcome: row.try_get(4)?,
+            created: row.try_get(5)?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Record {
+    admin: String,
+    action: String,
+    payload: Value,
+    outcome: String,
+    created: SystemTime,
+}
+
+/// Written by the danmaku flusher, in the order the actions were taken.
+static QUEUE: Mutex<Vec<Record>> = Mutex::new(Vec::new());
+
+pub async fn insert(record: &Record) -> Result<(), BB8Error> {
+    let mut conn = get_connection().await?;
+    let stmt = conn.prepare_static(INSERT_AUDIT.into()).await?;
+    conn.execute(
+        &stmt,
+        &[
+            &record.admin,
+            &record.action,
+            &record.payload,
+            &record.outcome,
+            &record.created,
+        ],
+    )
+    .await?;
+    Ok(())
+}
+
+pub fn record(admin: &str, action: &str, payload: Value, outcome: Result<(), ErrorCode>) {
+    let outcome = match outcome {
+        Ok(()) => "ok".to_owned(),
+        Err(code) => serde_json::to_value(code)
+            .ok()
+            .and_then(|code| code.as_str().map(str::to_owned))
+            .unwrap_or_default(),
+    };
+    QUEUE.lock().push(Record {
+        admin: admin.to_owned(),
+        action: action.to_owned(),
+        payload,
+        outcome,
+        created: SystemTime::now(),
+    });
+}
+
+pub async fn flush() {
+    let records = core::mem::take(&mut *QUEUE.lock());
+    if records.is_empty() {
+        return;
+    }
+
+    let mut written = 0;
+    if spool::backlog() == 0 {
+        for record in &records {
+            if let Err(e) = insert(record).await {
+                tracing::warn!(target: "ChaGPT-audit", "failed to record admin actions, spooling them: {e:?}");
+                break;
+            }
+            written += 1;
+        }
+    }
+    if written == records.len() {
+        return;
+    }
+
+    let entries: Vec<SpoolEntry> = records
+        .into_iter()
+        .skip(written)
+        .map(SpoolEntry::Audit)
+        .collect();
+    if let Err(e) = spool::append(&entries).await {
+        for entry in &entries {
+            if let SpoolEntry::Audit(record) = entry {
+                tracing::error!(
+                    target: "ChaGPT-audit",
+                    "failed to spool {} by {} ({}): {e:?}; payload: {}",
+                    record.action, record.admin, record.outcome, record.payload,
+                );
+            }
+        }
+    }
+}
+
+pub async fn list(before: Option<u32>, limit: u32) -> Result<Vec<Entry>, BB8Error> {
+    let mut conn = get_connection().await?;
+    let stmt = conn.prepare_static(GET_AUDIT.into()).await?;
+
+    let before = before.map_or(i32::MAX, |id| id as i32);
+    conn.query(&stmt, &[&before, &i64::from(limit)])
+        .await?
+        .iter()
+        .map(Entry::from_row)
+        .collect()
+}
+
+pub fn payload(entries: &[Entry]) -> String {
+    let entries = serde_json::to_string(entries).unwrap_or_else(|_| "[]".into());
+    format!(r#"4{{"type":"audit-log","entries":{entries}}}"#)
+}
@@ -0,0 +1,234 @@
+use std::{
+    net::IpAddr,
+    sync::LazyLock,
+    time::{Instant, SystemTime},
+};
+
+use actix_web::web;
+use ahash::HashMap;
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use parking_lot::Mutex;
+
+use crate::libs::{
+    constants::{ADMIN_SESSION_TTL, LOGIN_FAILURE_WINDOW, LOGIN_LOCKOUT, LOGIN_MAX_FAILURES},
+    db::{get_connection, BB8Error},
+    token,
+    util::millis,
+};
+
+use super::{admin::Role, error::ErrorCode};
+
+const GET_ADMIN: &str = "select password, role from admins where name = $1";
+
+pub struct Admin {
+    pub name: String,
+    pub role: Role,
+}
+
+struct Failures {
+    count: u32,
+    since: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Clients whose IP is unknown are not locked out, as only the admin name tried would tell them
+/// apart, and anyone could then lock a named admin out.
+static FAILURES: LazyLock<Mutex<HashMap<IpAddr, Failures>>> =
+    LazyLock::new(|| Mutex::new(HashMap::default()));
+
+/// Verified against when the name is unknown, so that a missing admin takes as long to reject
+/// as a wrong password.
+static DUMMY_HASH: LazyLock<String> = LazyLock::new(|| {
+    let salt = SaltString::from_b64("ZHVtbXlzYWx0ZHVtbXk").unwrap();
+    Argon2::default()
+        .hash_password(b"", &salt)
+        .map(|hash| hash.to_string())
+        .unwrap_or_default()
+});
+
+#[inline]
+fn is_over(f: &Failures, now: Instant) -> bool {
+    // the lockout is over, or the failures are too old to count
+    f.locked_until.map_or(now - f.since > LOGIN_FAILURE_WINDOW, |until| until <= now)
+}
+
+fn locked_out(ip: Option<IpAddr>) -> bool {
+    let Some(ip) = ip else {
+        return false;
+    };
+    let now = Instant::now();
+    let mut failures = FAILURES.lock();
+    let Some(f) = failures.get(&ip) else {
+        return false;
+    };
+    if is_over(f, now) {
+        failures.remove(&ip);
+        return false;
+    }
+    f.locked_until.is_some()
+}
+
+/// Counts the attempt as failed before the password is checked, so that attempts made in
+/// parallel cannot all get past the limit; a successful login clears the count again.
+fn reserve(ip: Option<IpAddr>) -> Result<(), ErrorCode> {
+    let Some(ip) = ip else {
+        return Ok(());
+    };
+    let now = Instant::now();
+    let count = {
+        let mut failures = FAILURES.lock();
+        if failures.get(&ip).is_some_and(|f| is_over(f, now)) {
+            failures.remove(&ip);
+        }
+        let f = failures.entry(ip).or_insert(Failures {
+            count: 0,
+            since: now,
+            locked_until: None,
+        });
+        if f.locked_until.is_some() {
+            return Err(ErrorCode::LockedOut);
+        }
+        f.count += 1;
+        if f.count >= LOGIN_MAX_FAILURES {
+            f.locked_until = Some(now + LOGIN_LOCKOUT);
+        }
+        f.count
+    };
+    if count >= LOGIN_MAX_FAILURES {
+        tracing::warn!(target: "ChaGPT-admin", "locking out {ip} after {count} failed logins");
+    }
+    Ok(())
+}
+
+async fn lookup(name: &str) -> Result<Option<(String, Role)>, ErrorCode> {
+    let res: Result<_, BB8Error> = async {
+        let mut conn = get_connection().await?;
+        let stmt = conn.prepare_static(GET_ADMIN.into()).await?;
+        let Some(row) = conn.query_opt(&stmt, &[&name]).await? else {
+            return Ok(None);
+        };
+        Ok(Some((row.try_get(0)?, Role::from_db(row.try_get(1)?))))
+    }
+    .await;
+
+    res.map_err(|e| {
+        tracing::error!(target: "ChaGPT-admin", "failed to look up admin {name:?}: {e:?}");
+        ErrorCode::StorageFailure
+    })
+}
+
+async fn verify(password: String, hash: String) -> bool {
+    web::block(move || {
+        PasswordHash::new(&hash).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    })
+    .await
+    .unwrap_or(false)
+}
+
+pub async fn login(name: String, password: String, ip: Option<IpAddr>) -> Result<Admin, ErrorCode> {
+    if locked_out(ip) {
+        return Err(ErrorCode::LockedOut);
+    }
+
+    // an unreachable database is nobody's fault, so it does not count as a failure
+    let (hash, role) = match lookup(&name).await? {
+        Some((hash, role)) => (hash, Some(role)),
+        None => (DUMMY_HASH.clone(), None),
+    };
+
+    reserve(ip)?;
+    let valid = verify(password, hash).await;
+
+    match role {
+        Some(role) if valid => {
+            if let Some(ip) = ip {
+                FAILURES.lock().remove(&ip);
+            }
+            Ok(Admin { name, role })
+        }
+        _ => {
+            if ip.is_none() {
+                tracing::warn!(target: "ChaGPT-admin", "failed login as {name:?} from an unknown IP");
+            }
+            Err(ErrorCode::Unauthorized)
+        }
+    }
+}
+
+pub fn session(admin: &Admin) -> (String, u128) {
+    let expires = millis(SystemTime::now() + ADMIN_SESSION_TTL);
+    let token = token::sign(&format!("s:{expires}:{}:{}", admin.role.as_str(), admin.name));
+    (token, expires)
+}
+
+/// The admin is looked up again, so that a removed admin is turned away and a changed role
+/// applies at once; only while the database is down does the token's role stand.
+pub async fn resume(session: String, ip: Option<IpAddr>) -> Result<Admin, ErrorCode> {
+    let parsed = token::verify(&session)
+        .and_then(|payload| payload.strip_prefix("s:"))
+        .and_then(|payload| {
+            let mut parts = payload.splitn(3, ':');
+            Some((parts.next()?.parse::<u128>().ok()?, parts.next()?, parts.next()?))
+        });
+    let Some((expires, role, name)) = parsed else {
+        reserve(ip)?;
+        return Err(ErrorCode::Unauthorized);
+    };
+    if locked_out(ip) {
+        return Err(ErrorCode::LockedOut);
+    }
+    if expires <= millis(SystemTime::now()) {
+        return Err(ErrorCode::SessionExpired);
+    }
+
+    let role = match lookup(name).await {
+        Ok(Some((_, role))) => role,
+        Ok(None) => {
+            tracing::warn!(target: "ChaGPT-admin", "session of removed admin {name:?} refused");
+            return Err(ErrorCode::Unauthorized);
+        }
+        Err(_) => Role::from_db(role),
+    };
+    Ok(Admin {
+        name: name.to_owned(),
+        role,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::{locked_out, reserve, FAILURES};
+    use crate::libs::{chagpt::error::ErrorCode, constants::LOGIN_MAX_FAILURES, logger};
+
+    #[test]
+    fn lockout() {
+        logger::init();
+
+        let ip = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        for _ in 1..LOGIN_MAX_FAILURES {
+            assert!(reserve(ip).is_ok());
+        }
+        assert!(!locked_out(ip));
+        // the last attempt is let through and locks out the ones after it
+        assert!(reserve(ip).is_ok());
+        assert!(locked_out(ip));
+        assert!(matches!(reserve(ip), Err(ErrorCode::LockedOut)));
+
+        for _ in 0..LOGIN_MAX_FAILURES {
+            assert!(reserve(None).is_ok());
+        }
+        assert!(!locked_out(None));
+
+        FAILURES.lock().clear();
+        assert!(!locked_out(ip));
+    }
+}
@@ -0,0 +1,371 @@
+use core::{pin::pin, sync::atomic::Ordering};
+use std::{sync::LazyLock, time::SystemTime};
+
+use bytestring::ByteString;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, Notify};
+
+use crate::libs::{
+    constants::{DANMAKU_BATCH_SIZE, DANMAKU_FLUSH_INTERVAL, DANMAKU_ID_BLOCK},
+    db::{get_connection, BB8Error},
+    metrics,
+};
+
+use super::{
+    admin, audit,
+    danmaku::Danmaku,
+    identity::Identity,
+    moderation,
+    spool::{self, Entry},
+    Emit,
+};
+
+const RESERVE_IDS: &str =
+    "select setval('danmakus_id_seq', greatest(nextval('danmakus_id_seq'), $2) + $1 - 1)";
+const INSERT_DANMAKUS: &str = "insert into danmakus (id, content, time, color, deleted, identity) \
+    select * from unnest($1::int4[], $2::text[], $3::timestamptz[], $4::int4[], $5::bool[], $6::int8[]) \
+    on conflict (id) do nothing returning id";
+const GET_DANMAKUS: &str = "select id, content, color, identity from danmakus where id = any($1)";
+// retracted and hidden rows are kept, so every query reading danmakus back has to skip them
+const RETRACT_DANMAKU: &str = "update danmakus set deleted = true where id = $1 and not deleted";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Row {
+    pub id: u32,
+    content: String,
+    time: SystemTime,
+    color: u32,
+    #[serde(default)]
+    deleted: bool,
+    #[serde(default)]
+    identity: u64,
+}
+
+/// Ids are handed out and rows are queued under the same lock, so the queue is always sorted by
+/// id and a single flusher writes them in that order. The lock is never held across a query.
+#[derive(Default)]
+struct State {
+    next: u32,
+    end: u32,
+    reserved: Option<(u32, u32)>,
+    reserving: bool,
+    queue: Vec<Row>,
+    in_flight: Vec<u32>,
+    retracted: Vec<u32>,
+}
+
+static STATE: LazyLock<Mutex<State>> = LazyLock::new(|| Mutex::new(State::default()));
+static FLUSH: Notify = Notify::const_new();
+static FLUSHED: Notify = Notify::const_new();
+
+pub async fn init() {
+    if let Some(next) = spool::init().await {
+        let mut state = STATE.lock().await;
+        state.next = next;
+        state.end = next;
+        state.reserving = true;
+        tokio::task::spawn(prefetch(next));
+    }
+}
+
+async fn reserve(next: u32) -> Result<(u32, u32), BB8Error> {
+    let mut conn = get_connection().await?;
+    let stmt = conn.prepare_static(RESERVE_IDS.into()).await?;
+
+    let row = conn
+        .query_one(&stmt, &[&(DANMAKU_ID_BLOCK as i64), &(next as i64)])
+        .await?;
+    let end = row.try_get::<_, i64>(0)? as u32 + 1;
+    if let Err(e) = spool::mark(end).await {
+        tracing::error!(target: "danmaku-batcher", "failed to persist the reserved ids: {e:?}");
+    }
+
+    Ok((end - DANMAKU_ID_BLOCK, end))
+}
+
+async fn prefetch(after: u32) {
+    let reserved = reserve(after).await;
+
+    let mut state = STATE.lock().await;
+    state.reserving = false;
+    match reserved {
+        Ok((next, end)) if next >= state.end => state.reserved = Some((next, end)),
+        // ids past `after` were handed out locally meanwhile, the next prefetch skips them
+        Ok(_) => (),
+        Err(e) => {
+            tracing::warn!(target: "danmaku-batcher", "failed to reserve ids: {e:?}");
+        }
+    }
+}
+
+pub async fn submit(
+    content: String,
+    color: u32,
+    identity: Identity,
+    hidden: bool,
+) -> Option<Danmaku> {
+    let mut state = STATE.lock().await;
+    // persisted once the lock is released, as the batcher must not wait for the disk
+    let mut local = None;
+
+    // nothing is known about the ids in use, which only happens on a first start
+    if state.next == 0 {
+        drop(state);
+        let reserved = reserve(0).await;
+        state = STATE.lock().await;
+        match reserved {
+            Ok((next, end)) if state.next == 0 => {
+                state.next = next;
+                state.end = end;
+            }
+            // another proposal got a block first, this one goes unused
+            Ok(_) => (),
+            Err(e) => {
+                tracing::error!(target: "danmaku-batcher", "failed to reserve ids: {e:?}");
+                if state.next == 0 {
+                    return None;
+                }
+            }
+        }
+    }
+
+    if state.next == state.end {
+        if let Some((next, end)) = state.reserved.take() {
+            state.next = next;
+            state.end = end;
+        } else {
+            // the database is slow or down, keep counting from the last known id; the
+            // sequence is moved past these ids by the next successful reservation
+            tracing::warn!(target: "danmaku-batcher", "no ids reserved in time, continuing locally");
+            state.end = state.next + DANMAKU_ID_BLOCK;
+            local = Some(state.end);
+        }
+    }
+    if state.reserved.is_none()
+        && !state.reserving
+        && state.end - state.next <= DANMAKU_ID_BLOCK / 2
+    {
+        state.reserving = true;
+        tokio::task::spawn(prefetch(state.end));
+    }
+
+    let id = state.next;
+    state.next += 1;
+    let time = SystemTime::now();
+    state.queue.push(Row {
+        id,
+        content: content.clone(),
+        time,
+        color,
+        deleted: hidden,
+        identity: identity.0,
+    });
+    if state.queue.len() >= DANMAKU_BATCH_SIZE {
+        FLUSH.notify_one();
+    }
+    drop(state);
+
+    if let Some(end) = local
+        && let Err(e) = spool::mark(end).await
+    {
+        tracing::error!(target: "danmaku-batcher", "failed to persist the local ids: {e:?}");
+    }
+
+    Some(Danmaku {
+        id,
+        content,
+        time,
+        color,
+        identity,
+    })
+}
+
+pub async fn write(rows: &[Row]) -> Result<(), BB8Error> {
+    let mut conn = get_connection().await?;
+    let stmt = conn.prepare_static(INSERT_DANMAKUS.into()).await?;
+
+    let ids: Vec<i32> = rows.iter().map(|row| row.id as i32).collect();
+    let contents: Vec<&str> = rows.iter().map(|row| row.content.as_str()).collect();
+    let times: Vec<SystemTime> = rows.iter().map(|row| row.time).collect();
+    let colors: Vec<i32> = rows.iter().map(|row| row.color as i32).collect();
+    let deleted: Vec<bool> = rows.iter().map(|row| row.deleted).collect();
+    let identities: Vec<i64> = rows.iter().map(|row| row.identity as i64).collect();
+
+    let inserted = conn
+        .query(&stmt, &[&ids, &contents, &times, &colors, &deleted, &identities])
+        .await?;
+    if inserted.len() < rows.len() {
+        let inserted: Vec<i32> = inserted.iter().filter_map(|row| row.try_get(0).ok()).collect();
+        let taken: Vec<i32> = ids.into_iter().filter(|id| !inserted.contains(id)).collect();
+        let stmt = conn.prepare_static(GET_DANMAKUS.into()).await?;
+        let stored = conn.query(&stmt, &[&taken]).await?;
+        for row in rows.iter().filter(|row| taken.contains(&(row.id as i32))) {
+            // a row written before, by a replay which stopped or crashed before it could move on
+            if stored.iter().any(|stored| is_same(row, stored)) {
+                continue;
+            }
+            tracing::error!(target: "danmaku-batcher", "danmaku id {} is already taken, setting the row aside", row.id);
+            if let Err(e) = spool::dead_letter(&Entry::Danmaku(row.clone())).await {
+                tracing::error!(target: "danmaku-batcher", "failed to keep a dead letter: {e:?}");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_same(row: &Row, stored: &tokio_postgres::Row) -> bool {
+    stored.try_get::<_, i32>(0).is_ok_and(|id| id == row.id as i32)
+        && stored.try_get::<_, &str>(1).is_ok_and(|content| content == row.content)
+        && stored.try_get::<_, i32>(2).is_ok_and(|color| color == row.color as i32)
+        && stored.try_get::<_, i64>(3).is_ok_and(|identity| identity == row.identity as i64)
+}
+
+pub async fn write_retraction(id: u32) -> Result<bool, BB8Error> {
+    let mut conn = get_connection().await?;
+    let stmt = conn.prepare_static(RETRACT_DANMAKU.into()).await?;
+
+    Ok(conn.execute(&stmt, &[&(id as i32)]).await? > 0)
+}
+
+/// `handed_out` tells whether the id was ever assigned, for when the database cannot say.
+async fn store_retraction(id: u32, handed_out: bool) -> bool {
+    // the row may still be spooled, in which case this has to be replayed after it
+    if spool::backlog() == 0 {
+        match write_retraction(id).await {
+            Ok(retracted) => return retracted,
+            Err(e) => {
+                tracing::warn!(target: "danmaku-batcher", "failed to retract {id}, spooling it: {e:?}");
+            }
+        }
+    }
+    if !handed_out {
+        return false;
+    }
+    if let Err(e) = spool::append(&[Entry::Retract { id }]).await {
+        tracing::error!(target: "danmaku-batcher", "failed to spool retraction of {id}: {e:?}");
+    }
+    true
+}
+
+pub async fn retract(id: u32) -> bool {
+    let handed_out = {
+        let mut state = STATE.lock().await;
+        if let Some(row) = state.queue.iter_mut().find(|row| row.id == id) {
+            return !core::mem::replace(&mut row.deleted, true);
+        }
+        // applied by the flusher once the rows it is writing have landed somewhere
+        if state.in_flight.contains(&id) {
+            if state.retracted.contains(&id) {
+                return false;
+            }
+            state.retracted.push(id);
+            return true;
+        }
+        id < state.next
+    };
+    store_retraction(id, handed_out).await
+}
+
+/// Waits until the row has left the queue, false if it may have been spooled instead of written.
+pub async fn written(id: u32) -> bool {
+    loop {
+        let mut flushed = pin!(FLUSHED.notified());
+        flushed.as_mut().enable();
+        {
+            let state = STATE.lock().await;
+            if !state.queue.iter().any(|row| row.id == id) && !state.in_flight.contains(&id) {
+                break;
+            }
+        }
+        FLUSH.notify_one();
+        flushed.await;
+    }
+    spool::backlog() == 0
+}
+
+async fn spool_rows(rows: Vec<Row>) {
+    let entries: Vec<Entry> = rows.into_iter().map(Entry::Danmaku).collect();
+    let Err(e) = spool::append(&entries).await else {
+        return;
+    };
+    tracing::error!(target: "danmaku-batcher", "failed to spool {} danmakus: {e:?}", entries.len());
+
+    let mut rows: Vec<Row> = entries
+        .into_iter()
+        .filter_map(|entry| match entry {
+            Entry::Danmaku(row) => Some(row),
+            _ => None,
+        })
+        .collect();
+    let mut state = STATE.lock().await;
+    rows.append(&mut state.queue);
+    state.queue = rows;
+}
+
+async fn settle() {
+    let mut retracted = Vec::new();
+    {
+        let mut state = STATE.lock().await;
+        state.in_flight.clear();
+        for id in core::mem::take(&mut state.retracted) {
+            match state.queue.iter_mut().find(|row| row.id == id) {
+                Some(row) => row.deleted = true,
+                None => retracted.push(id),
+            }
+        }
+    }
+    for id in retracted {
+        store_retraction(id, true).await;
+    }
+}
+
+async fn flush(mut rows: Vec<Row>) {
+    // anything spooled is older than the queue, so it has to reach the database first
+    if spool::backlog() > 0 && !spool::replay().await {
+        if !rows.is_empty() {
+            spool_rows(rows).await;
+        }
+        return;
+    }
+
+    let mut written = 0;
+    while written < rows.len() {
+        let end = rows.len().min(written + DANMAKU_BATCH_SIZE);
+        if let Err(e) = write(&rows[written..end]).await {
+            tracing::error!(target: "danmaku-batcher", "failed to flush danmakus: {e:?}");
+            break;
+        }
+        written = end;
+    }
+    if written == rows.len() {
+        return;
+    }
+
+    let pending = rows.len() - written;
+    metrics::DANMAKU_FLUSH_FAILURES.fetch_add(1, Ordering::Relaxed);
+    let payload = Emit(ByteString::from(format!(
+        r#"4{{"type":"flush-failed","pending":{pending}}}"#
+    )));
+    admin::notify(payload);
+
+    rows.drain(..written);
+    spool_rows(rows).await;
+}
+
+pub async fn flusher() {
+    loop {
+        let _ = tokio::time::timeout(DANMAKU_FLUSH_INTERVAL, FLUSH.notified()).await;
+
+        let rows = {
+            let mut state = STATE.lock().await;
+            let rows = core::mem::take(&mut state.queue);
+            state.in_flight = rows.iter().filter(|row| !row.deleted).map(|row| row.id).collect();
+            rows
+        };
+        flush(rows).await;
+        settle().await;
+        FLUSHED.notify_waiters();
+        moderation::flush().await;
+        audit::flush().await;
+    }
+}
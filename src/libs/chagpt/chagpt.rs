@@ -1,41 +1,273 @@
-use std::{sync::LazyLock, time::SystemTime};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::{sync::LazyLock, time::Instant};
 
-use actix::{fut::wrap_future, ActorFutureExt, Addr, AsyncContext, Handler};
+use actix::{fut::wrap_future, ActorContext, ActorFutureExt, AsyncContext};
 use actix_web::web::Bytes;
 use actix_web_actors::ws;
-use ahash::HashSet;
+use ahash::HashMap;
 use bytestring::ByteString;
 use parking_lot::RwLock;
 use serde::Deserialize;
 
-use super::{admin::CURRENT_ADMIN, danmaku::Danmaku, repertoire::REPERTOIRE, Emit};
-use crate::libs::ws::{AppWsActor, WsActor};
+use super::{
+    ack::{self, Claim, Seen},
+    admin, announcement, content,
+    danmaku::Danmaku,
+    emitter,
+    error::ErrorCode,
+    identity::Identity,
+    mode,
+    moderation::{self, BanKind, Target},
+    repertoire::{self, DanmakuPolicy, REPERTOIRE},
+    scheduler::{Item, Priority},
+    stats, Emit,
+};
+use crate::libs::{
+    constants::{NONCE_MAX_LENGTH, ONLINE_INTERVAL},
+    util::millis,
+    ws::{AppWsActor, Peer, WsActor},
+};
 
-pub struct ChaGPTActor;
+pub struct ChaGPTActor {
+    identity: Identity,
+    ip: Option<String>,
+}
+
+impl ChaGPTActor {
+    #[inline]
+    pub const fn new(identity: Identity, ip: Option<String>) -> Self {
+        Self { identity, ip }
+    }
+
+    fn propose(&self, ctx: &mut ChaGPTContext, content: &str, color: u32, nonce: Option<String>) {
+        if nonce.as_ref().is_some_and(|nonce| nonce.len() > NONCE_MAX_LENGTH) {
+            ctx.text(ErrorCode::Malformed.event().0);
+            return;
+        }
+        let identity = self.identity;
+        // a retry gets the answer to the first attempt, whatever has changed since
+        let claim = match nonce {
+            Some(ref nonce) => match ack::begin(identity, nonce) {
+                Seen::New(claim) => Some(claim),
+                Seen::Pending => {
+                    ctx.text(ack::pending(nonce).0);
+                    return;
+                }
+                Seen::Done(ack) => {
+                    ctx.text(ack.0);
+                    return;
+                }
+            },
+            None => None,
+        };
+        // a rejection drops the claim, so a retry is tried again once whatever stopped it is over
+        let reject = |ctx: &mut ChaGPTContext, code: ErrorCode| {
+            ctx.text(ack::rejected(nonce.as_deref(), code).0);
+        };
+
+        let ban = moderation::check(identity, self.ip.as_deref());
+        match ban {
+            Some(BanKind::Ban) => {
+                reject(ctx, ErrorCode::Banned);
+                ctx.stop();
+                return;
+            }
+            Some(BanKind::Mute) => {
+                reject(ctx, ErrorCode::Muted);
+                return;
+            }
+            Some(BanKind::Shadow) | None => (),
+        }
+        let shadow = ban == Some(BanKind::Shadow);
+
+        let mode = mode::get();
+        let policy = repertoire::policy();
+        if mode.closed || policy == DanmakuPolicy::Closed {
+            reject(ctx, ErrorCode::ChatClosed);
+            return;
+        }
+
+        let content = content::sanitize(content);
+        if let Err(code) = content::check(&content, mode.max_length) {
+            reject(ctx, code);
+            return;
+        }
+        if mode.emoji_only && !content::is_emoji_only(&content) {
+            reject(ctx, ErrorCode::EmojiOnly);
+            return;
+        }
+        let taken = Instant::now();
+        if !mode::take_slot(identity, taken) {
+            reject(ctx, ErrorCode::RateLimited);
+            return;
+        }
+
+        ctx.wait(wrap_future(Danmaku::insert(content, color, identity, shadow)).map(
+            move |danmaku, actor: &mut ChaGPTWsActor, ctx: &mut ChaGPTContext| {
+                let Some(danmaku) = danmaku else {
+                    mode::return_slot(identity, taken);
+                    ctx.text(ack::rejected(nonce.as_deref(), ErrorCode::StorageFailure).0);
+                    return;
+                };
+                reply(ctx, claim, ack::accepted(nonce.as_deref(), danmaku.id));
+                publish(&danmaku, actor.app.ip.as_deref(), shadow, policy);
+            },
+        ));
+    }
+}
 
 pub type ChaGPTWsActor = WsActor<ChaGPTActor>;
 pub type ChaGPTContext = ws::WebsocketContext<ChaGPTWsActor>;
-type Set = HashSet<Addr<ChaGPTWsActor>>;
+type Map = HashMap<Peer<ChaGPTActor>, (Identity, Option<String>)>;
+
+pub static ACTORS: LazyLock<RwLock<Map>> = LazyLock::new(|| RwLock::new(Map::default()));
+
+pub fn broadcast(payload: &Emit, critical: bool) {
+    let guard = ACTORS.read();
+    for actor in guard.keys() {
+        let res = if critical {
+            actor.send_critical(payload.clone())
+        } else {
+            actor.send(payload.clone())
+        };
+        if let Err(e) = res {
+            tracing::error!(target: "broadcast", err = ?e);
+        }
+    }
+}
+
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+#[inline]
+fn online_payload(count: usize) -> String {
+    format!(r#"4{{"type":"online","count":{count}}}"#)
+}
 
-pub static ACTORS: LazyLock<RwLock<Set>> = LazyLock::new(|| RwLock::new(Set::default()));
+pub async fn online_reporter() {
+    loop {
+        tokio::time::sleep(ONLINE_INTERVAL).await;
+
+        let count = ACTORS.read().len();
+        if ONLINE.swap(count, Ordering::SeqCst) != count {
+            broadcast(&Emit(ByteString::from(online_payload(count))), false);
+        }
+    }
+}
+
+fn echo(identity: Identity, payload: &Emit) {
+    let guard = ACTORS.read();
+    for (actor, _) in guard.iter().filter(|(_, (id, _))| *id == identity) {
+        if let Err(e) = actor.send(payload.clone()) {
+            tracing::error!(target: "echo", err = ?e);
+        }
+    }
+}
+
+/// Mutes and shadow bans are checked on every proposal and need nothing of the kind.
+pub fn disconnect(target: &Target) {
+    let guard = ACTORS.read();
+    for (actor, (identity, ip)) in guard.iter() {
+        let banned = match *target {
+            Target::Identity(id) => id == *identity,
+            Target::Ip(ref addr) => ip.as_deref() == Some(addr.as_str()),
+        };
+        if banned {
+            let _ = actor.send_critical(ErrorCode::Banned.event());
+            actor.close();
+        }
+    }
+}
 
 #[derive(Deserialize)]
 #[serde(tag = "type")]
 enum Message {
     #[serde(rename = "propose")]
-    Propose { content: String, color: u32 },
+    Propose {
+        content: String,
+        color: u32,
+        nonce: Option<String>,
+    },
+}
+
+fn reply(ctx: &mut ChaGPTContext, claim: Option<Claim>, ack: Emit) {
+    if let Some(claim) = claim {
+        claim.finish(&ack);
+    }
+    ctx.text(ack.0);
+}
+
+fn publish(danmaku: &Danmaku, ip: Option<&str>, shadow: bool, policy: DanmakuPolicy) {
+    let Ok(content) = serde_json::to_string(&danmaku.content) else {
+        return;
+    };
+    let timestamp = millis(danmaku.time);
+
+    let payload = Emit(ByteString::from(format!(
+        r#"4{{"type":"danmaku","id":{},"content":{content},"time":{timestamp},"color":{}}}"#,
+        danmaku.id, danmaku.color
+    )));
+    if shadow {
+        echo(danmaku.identity, &payload);
+        return;
+    }
+    let ip = serde_json::to_string(&ip).unwrap_or_else(|_| "null".into());
+    let admin_payload = Emit(ByteString::from(format!(
+        r#"4{{"type":"danmaku","id":{},"content":{content},"time":{timestamp},"color":{},"identity":"{}","ip":{ip}}}"#,
+        danmaku.id, danmaku.color, danmaku.identity
+    )));
+    stats::accepted(Instant::now());
+
+    let item = Item {
+        id: Some(danmaku.id),
+        payload: Emit(ByteString::from(format!(
+            r#"4{{"id":{},"content":{content},"color":{}}}"#,
+            danmaku.id, danmaku.color
+        ))),
+        priority: Priority::Normal,
+    };
+    match policy {
+        // closed programs turn proposals away before they get here
+        DanmakuPolicy::Open | DanmakuPolicy::Closed => {
+            broadcast(&payload, false);
+            emitter::emit_unchecked(&item);
+        }
+        DanmakuPolicy::Moderated => {
+            echo(danmaku.identity, &payload);
+            moderation::hold(danmaku.id, danmaku.time);
+        }
+        DanmakuPolicy::AutoForward => {
+            broadcast(&payload, false);
+            emitter::route(item, None);
+        }
+    }
+    admin::notify(admin_payload);
 }
 
 impl AppWsActor for ChaGPTActor {
-    fn started(&mut self, ctx: &mut ChaGPTContext, hash: u64) {
-        {
+    fn started(&mut self, ctx: &mut ChaGPTContext, peer: &Peer<Self>) {
+        let hash = peer.addr_hash();
+        let online = {
             let mut guard = ACTORS.write();
-            if guard.insert(ctx.address()) {
+            if guard
+                .insert(peer.clone(), (self.identity, self.ip.clone()))
+                .is_none()
+            {
                 tracing::debug!(target: "ChaGPT-actor", "\x1b[33mINSERT \x1b[32m{hash:#x}\x1b[33m, size => \x1b[32m{}\x1b[0m", guard.len());
             } else {
                 tracing::error!(target: "ChaGPT-actor", "\x1b[1;31mINSERT \x1b[32m{hash:#x}\x1b[31m, size => \x1b[32m{}\x1b[0m", guard.len());
             }
+            guard.len()
+        };
+        // registered all the same, so that stopping finds it as for any other socket
+        if moderation::check(self.identity, self.ip.as_deref()) == Some(BanKind::Ban) {
+            ctx.text(ErrorCode::Banned.event().0);
+            ctx.stop();
+            return;
         }
+        ctx.text(format!(
+            r#"4{{"type":"identity","token":"{}"}}"#,
+            self.identity.token()
+        ));
         if let Some(r) = REPERTOIRE.read().as_ref()
             && let Ok(programs) = serde_json::to_string(&r.programs)
         {
@@ -45,13 +277,19 @@ impl AppWsActor for ChaGPTActor {
             );
             ctx.text(payload);
         }
+        for payload in announcement::pinned() {
+            ctx.text(payload.0);
+        }
+        ctx.text(mode::payload());
+        ctx.text(online_payload(online));
     }
 
-    fn stopped(&mut self, ctx: &mut ChaGPTContext, hash: u64) {
+    fn stopped(&mut self, _ctx: &mut ChaGPTContext, peer: &Peer<Self>) {
+        let hash = peer.addr_hash();
         let mut guard = ACTORS.write();
-        if guard.remove(&ctx.address()) {
+        if guard.remove(peer).is_some() {
             tracing::debug!(target: "ChaGPT-actor", "\x1b[33mREMOVE \x1b[32m{hash:#x}\x1b[33m, size => \x1b[32m{}\x1b[0m", guard.len());
-        } else {
+        } else if !peer.is_evicted() {
             tracing::error!(target: "ChaGPT-actor", "\x1b[1;31mREMOVE \x1b[32m{hash:#x}\x1b[31m, size => \x1b[32m{}\x1b[0m", guard.len());
         }
     }
@@ -60,48 +298,15 @@ impl AppWsActor for ChaGPTActor {
         tracing::debug!(target: "ChaGPT-actor", "handle text with length {}", text.len());
 
         let Ok(msg): Result<Message, _> = serde_json::from_str(text) else {
+            ctx.text(ErrorCode::Malformed.event().0);
             return;
         };
         match msg {
-            Message::Propose { content, color } if content.chars().count() <= 128 => {
-                ctx.wait(wrap_future(Danmaku::insert(content, color)).map(
-                    |danmaku, _actor, _ctx| {
-                        let Some(danmaku) = danmaku else { return };
-
-                        let timestamp = unsafe {
-                            danmaku
-                                .time
-                                .duration_since(SystemTime::UNIX_EPOCH)
-                                .unwrap_unchecked()
-                                .as_millis()
-                        };
-
-                        let Ok(content) = serde_json::to_string(&danmaku.content) else {
-                            return;
-                        };
-
-                        let payload = Emit(ByteString::from(format!(
-                            r#"4{{"type":"danmaku","id":{},"content":{content},"time":{timestamp},"color":{}}}"#,
-                            danmaku.id, danmaku.color
-                        )));
-
-                        {
-                            let guard = ACTORS.read();
-                            for actor in &*guard {
-                                if let Err(e) = actor.do_send(payload.clone()) {
-                                    tracing::error!(target: "danmaku-broadcast", err = ?e);
-                                }
-                            }
-                        }
-                        if let Some(ref addr) = *CURRENT_ADMIN.read() {
-                            if let Err(e) = addr.do_send(payload) {
-                                tracing::error!(target: "danmaku-to-admin", err = ?e);
-                            }
-                        }
-                    },
-                ));
-            }
-            _ => (),
+            Message::Propose {
+                content,
+                color,
+                nonce,
+            } => self.propose(ctx, &content, color, nonce),
         }
     }
 
@@ -109,12 +314,3 @@ impl AppWsActor for ChaGPTActor {
         tracing::debug!(target: "ChaGPT-actor", "handle binary with length {}", bin.len());
     }
 }
-
-impl Handler<Emit> for ChaGPTWsActor {
-    type Result = ();
-
-    #[inline]
-    fn handle(&mut self, msg: Emit, ctx: &mut Self::Context) -> Self::Result {
-        ctx.text(msg.0);
-    }
-}
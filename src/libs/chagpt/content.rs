@@ -0,0 +1,123 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::libs::constants::{CONTENT_MAX_GRAPHEME_CHARS, CONTENT_MAX_WIDTH};
+
+use super::error::ErrorCode;
+
+/// General category Cf. The zero-width joiner and non-joiner are left out, as emoji sequences and
+/// scripts such as Persian need them, and so are the tag characters, see [`is_tag`].
+#[inline]
+const fn is_format(c: char) -> bool {
+    matches!(c, '\u{00ad}' | '\u{0600}'..='\u{0605}' | '\u{061c}' | '\u{06dd}' | '\u{070f}'
+        | '\u{0890}' | '\u{0891}' | '\u{08e2}' | '\u{180e}' | '\u{200b}'
+        | '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2060}'..='\u{2064}'
+        | '\u{2066}'..='\u{206f}' | '\u{feff}' | '\u{fff9}'..='\u{fffb}' | '\u{110bd}'
+        | '\u{110cd}' | '\u{13430}'..='\u{1343f}' | '\u{1bca0}'..='\u{1bca3}'
+        | '\u{1d173}'..='\u{1d17a}' | '\u{e0001}')
+}
+
+/// Invisible on their own, but spelling out subdivision flags such as 🏴󠁧󠁢󠁥󠁮󠁧󠁿 after a base emoji.
+#[inline]
+const fn is_tag(c: char) -> bool {
+    matches!(c, '\u{e0020}'..='\u{e007f}')
+}
+
+pub fn sanitize(raw: &str) -> String {
+    let mut out = String::with_capacity(raw.len());
+    for grapheme in raw.graphemes(true) {
+        let emoji = grapheme.chars().next().is_some_and(is_pictographic);
+        for c in grapheme.chars().take(CONTENT_MAX_GRAPHEME_CHARS) {
+            match c {
+                '\n' | '\r' | '\t' | '\u{2028}' | '\u{2029}' => out.push(' '),
+                c if c.is_control() || is_format(c) || (is_tag(c) && !emoji) => (),
+                c => out.push(c),
+            }
+        }
+        // a cut emoji sequence must not end in a joiner, which would glue on the next glyph
+        if grapheme.chars().count() > CONTENT_MAX_GRAPHEME_CHARS && out.ends_with('\u{200d}') {
+            out.pop();
+        }
+    }
+    out.trim().to_owned()
+}
+
+/// Emoji sequences count as one wide glyph however many code points they are made of.
+#[inline]
+fn grapheme_width(grapheme: &str) -> usize {
+    grapheme.width().clamp(1, 2)
+}
+
+pub fn check(content: &str, max_graphemes: usize) -> Result<(), ErrorCode> {
+    if content.is_empty() {
+        return Err(ErrorCode::Malformed);
+    }
+
+    let mut count = 0;
+    let mut width = 0;
+    for grapheme in content.graphemes(true) {
+        count += 1;
+        width += grapheme_width(grapheme);
+        if count > max_graphemes || width > CONTENT_MAX_WIDTH {
+            return Err(ErrorCode::TooLong);
+        }
+    }
+    Ok(())
+}
+
+/// Modifiers, flags and keycaps are covered by their base character.
+#[inline]
+const fn is_pictographic(c: char) -> bool {
+    matches!(c, '\u{00a9}' | '\u{00ae}' | '\u{203c}' | '\u{2049}' | '\u{2122}' | '\u{2139}'
+        | '\u{2194}'..='\u{21aa}' | '\u{2300}'..='\u{23ff}' | '\u{24c2}' | '\u{25aa}'..='\u{25fe}'
+        | '\u{2600}'..='\u{27bf}' | '\u{2934}' | '\u{2935}' | '\u{2b00}'..='\u{2bff}'
+        | '\u{3030}' | '\u{303d}' | '\u{3297}' | '\u{3299}' | '\u{1f000}'..='\u{1faff}')
+}
+
+pub fn is_emoji_only(content: &str) -> bool {
+    content.graphemes(true).all(|grapheme| {
+        let mut chars = grapheme.chars();
+        match chars.next() {
+            Some(c) if c.is_whitespace() || is_pictographic(c) => true,
+            // keycaps such as 1️⃣ start with a plain digit
+            Some('0'..='9' | '#' | '*') => chars.any(|c| c == '\u{20e3}'),
+            _ => false,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, is_emoji_only, sanitize};
+    use crate::libs::logger;
+
+    #[test]
+    fn content() {
+        logger::init();
+
+        assert_eq!(sanitize(" a\u{202e}b\nc\u{0007} "), "ab c");
+        assert_eq!(sanitize("a\u{200b}b\u{2060}c\u{feff}d\u{00ad}"), "abcd");
+        assert_eq!(sanitize("👨\u{200d}👩"), "👨\u{200d}👩");
+        assert_eq!(sanitize("e\u{301}"), "e\u{301}");
+        assert_eq!(sanitize("می\u{200c}خواهم"), "می\u{200c}خواهم");
+        assert_eq!(sanitize(&format!("a{}", "\u{301}".repeat(64))).chars().count(), 16);
+        // subdivision flags and the longest emoji sequences are kept whole
+        let england = "🏴\u{e0067}\u{e0062}\u{e0065}\u{e006e}\u{e0067}\u{e007f}";
+        assert_eq!(sanitize(england), england);
+        let kiss = "👩🏽\u{200d}❤\u{fe0f}\u{200d}💋\u{200d}👨🏻";
+        assert_eq!(sanitize(kiss), kiss);
+        assert_eq!(sanitize("a\u{e0067}\u{e007f}"), "a");
+
+        // a family emoji is a single grapheme of two columns
+        assert!(check(&"👨\u{200d}👩\u{200d}👧".repeat(64), 128).is_ok());
+        assert!(check(&"a".repeat(128), 128).is_ok());
+        assert!(check(&"a".repeat(129), 128).is_err());
+        assert!(check(&"中".repeat(96), 128).is_ok());
+        assert!(check(&"中".repeat(97), 128).is_err());
+        assert!(check("", 128).is_err());
+
+        assert!(is_emoji_only("👍 🎉👨\u{200d}👩\u{200d}👧 🇨🇳 1\u{fe0f}\u{20e3} ❤\u{fe0f}"));
+        assert!(!is_emoji_only("好👍"));
+        assert!(!is_emoji_only("1"));
+    }
+}
@@ -1,34 +1,29 @@
 use std::time::SystemTime;
 
-use crate::libs::db::get_connection;
-
-const INSERT_DANMAKU: &str =
-    "insert into danmakus (content, time, color) values ($1, $2, $3) returning id";
+use super::{batcher, identity::Identity};
 
 pub struct Danmaku {
     pub id: u32,
     pub content: String,
     pub time: SystemTime,
     pub color: u32,
+    pub identity: Identity,
 }
 
 impl Danmaku {
-    pub async fn insert(content: String, color: u32) -> Option<Self> {
-        let mut conn = get_connection().await.ok()?;
-
-        let stmt = conn.prepare_static(INSERT_DANMAKU.into()).await.ok()?;
-        let time = SystemTime::now();
-        let row = conn
-            .query_one(&stmt, &[&content, &time, &(color as i32)])
-            .await
-            .ok()?;
-        let id = row.try_get::<_, i32>(0).ok()? as u32;
+    /// Hidden danmakus (from shadow-banned senders) are stored as deleted.
+    #[inline]
+    pub async fn insert(
+        content: String,
+        color: u32,
+        identity: Identity,
+        hidden: bool,
+    ) -> Option<Self> {
+        batcher::submit(content, color, identity, hidden).await
+    }
 
-        Some(Self {
-            id,
-            content,
-            time,
-            color,
-        })
+    #[inline]
+    pub async fn retract(id: u32) -> bool {
+        batcher::retract(id).await
     }
 }
@@ -0,0 +1,110 @@
+use core::future::Future;
+
+use ahash::HashMap;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::types::Json;
+
+use crate::libs::db::{get_connection, BB8Error};
+
+const GET_SETTINGS: &str = "select screen, data from display_settings";
+const UPDATE_SETTINGS: &str = "insert into display_settings (screen, data) values ($1, $2) \
+    on conflict (screen) do update set data = excluded.data";
+const CLEAR_OVERRIDES: &str = "delete from display_settings where screen <> $1";
+
+pub const ALL_SCREENS: &str = "*";
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Settings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scroll_speed: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opacity: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lane_density: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub font_size: Option<u32>,
+}
+
+impl Settings {
+    pub fn is_valid(&self) -> bool {
+        self.scroll_speed.map_or(true, |speed| speed > 0.0)
+            && self.opacity.map_or(true, |opacity| (0.0..=1.0).contains(&opacity))
+            && self.lane_density.map_or(true, |density| density > 0.0)
+            && self.font_size.map_or(true, |size| size > 0)
+    }
+
+    fn merge(self, over: &Self) -> Self {
+        Self {
+            scroll_speed: over.scroll_speed.or(self.scroll_speed),
+            opacity: over.opacity.or(self.opacity),
+            lane_density: over.lane_density.or(self.lane_density),
+            font_size: over.font_size.or(self.font_size),
+        }
+    }
+}
+
+static SETTINGS: RwLock<Option<HashMap<String, Settings>>> = RwLock::new(None);
+
+pub async fn init() -> Result<(), BB8Error> {
+    let mut conn = get_connection().await?;
+    let stmt = conn.prepare_static(GET_SETTINGS.into()).await?;
+
+    let mut settings = HashMap::default();
+    for row in conn.query(&stmt, &[]).await? {
+        let Json(data) = row.try_get(1)?;
+        settings.insert(row.try_get(0)?, data);
+    }
+
+    *SETTINGS.write() = Some(settings);
+    Ok(())
+}
+
+pub fn get(screen: &str) -> Settings {
+    let guard = SETTINGS.read();
+    let Some(ref settings) = *guard else {
+        return Settings::default();
+    };
+    let all = settings.get(ALL_SCREENS).cloned().unwrap_or_default();
+    match settings.get(screen) {
+        Some(own) => all.merge(own),
+        None => all,
+    }
+}
+
+async fn store(screen: &str, data: &Settings) -> Result<(), BB8Error> {
+    let mut conn = get_connection().await?;
+    let stmt = conn.prepare_static(UPDATE_SETTINGS.into()).await?;
+    conn.execute(&stmt, &[&screen, &Json(data)]).await?;
+
+    if screen == ALL_SCREENS {
+        let stmt = conn.prepare_static(CLEAR_OVERRIDES.into()).await?;
+        conn.execute(&stmt, &[&ALL_SCREENS]).await?;
+    }
+    Ok(())
+}
+
+/// Applied in memory right away, even if the returned future fails to store them.
+pub fn update(
+    screen: Option<String>,
+    data: Settings,
+) -> impl Future<Output = Result<(), BB8Error>> {
+    let screen = screen.unwrap_or_else(|| ALL_SCREENS.to_owned());
+    {
+        let mut guard = SETTINGS.write();
+        let settings = guard.get_or_insert_with(HashMap::default);
+        if screen == ALL_SCREENS {
+            settings.clear();
+        }
+        settings.insert(screen.clone(), data.clone());
+    }
+
+    async move { store(&screen, &data).await }
+}
+
+pub fn payload(screen: Option<&str>, data: &Settings) -> String {
+    let settings = serde_json::to_string(data).unwrap_or_else(|_| "{}".into());
+    let screen = serde_json::to_string(&screen).unwrap_or_else(|_| "null".into());
+    format!(r#"4{{"type":"display-settings","screen":{screen},"settings":{settings}}}"#)
+}
@@ -1,47 +1,448 @@
-use actix::{Addr, AsyncContext, Handler};
+use std::{collections::VecDeque, sync::LazyLock, time::Instant};
+
 use actix_web::web::Bytes;
 use actix_web_actors::ws;
-use parking_lot::RwLock;
+use ahash::HashMap;
+use bytestring::ByteString;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
 
 use crate::libs::{
-    constants::EMITTER_SECRET,
-    ws::{AppWsActor, WsActor},
+    config::CONFIG,
+    constants::{EMITTER_SECRET, EMITTER_TICK},
+    ws::{AppWsActor, Peer, WsActor},
+};
+
+use super::{
+    admin, display,
+    error::ErrorCode,
+    mode,
+    scheduler::{self, Item, Scheduler},
+    Emit,
 };
 
-use super::Emit;
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Filter {
+    #[default]
+    Approved,
+    All,
+}
+
+/// Each screen's queue has its own lock, so that pacing one screen only needs the registry
+/// for reading.
+pub struct Screen {
+    pub name: String,
+    pub filter: Filter,
+    queue: Mutex<Scheduler>,
+}
+
+#[derive(Serialize)]
+struct ScreenState<'a> {
+    name: &'a str,
+    filter: Filter,
+    queue: scheduler::State,
+}
+
+/// Screens that predate names log in with the bare secret and are taken as the main one.
+const DEFAULT_SCREEN: &str = "main";
+
+#[derive(Deserialize)]
+struct Login {
+    secret: String,
+    name: String,
+    #[serde(default)]
+    filter: Filter,
+}
 
-pub struct DanmakuEmitter;
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "login")]
+struct LoginEvent<'a> {
+    name: &'a str,
+    filter: Filter,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum Message {
+    #[serde(rename = "displayed")]
+    Displayed { id: u32 },
+}
+
+#[derive(Default)]
+pub struct DanmakuEmitter {
+    name: Option<String>,
+    peer: Option<Peer<Self>>,
+}
 pub type DanmakuEmitterWs = WsActor<DanmakuEmitter>;
 pub type DanmakuEmitterContext = ws::WebsocketContext<DanmakuEmitterWs>;
-type MaybeAddress = Option<Addr<DanmakuEmitterWs>>;
 
-pub static CURRENT_EMITTER: RwLock<MaybeAddress> = RwLock::new(None);
+type Map = HashMap<Peer<DanmakuEmitter>, Screen>;
 
-impl AppWsActor for DanmakuEmitter {
-    fn started(&mut self, _: &mut DanmakuEmitterContext, _: u64) {}
+pub static EMITTERS: LazyLock<RwLock<Map>> = LazyLock::new(|| RwLock::new(Map::default()));
+
+struct Pending {
+    item: Item,
+    /// The screens still missing it; `None` means any screen will do.
+    screens: Option<Vec<String>>,
+    queued: Instant,
+}
+
+static PENDING: Mutex<VecDeque<Pending>> = Mutex::new(VecDeque::new());
+
+/// When each screen name last logged in or went away, so that danmakus for all screens are
+/// also kept for the ones reconnecting.
+static SEEN: LazyLock<Mutex<HashMap<String, Instant>>> =
+    LazyLock::new(|| Mutex::new(HashMap::default()));
 
-    fn stopped(&mut self, ctx: &mut DanmakuEmitterContext, _: u64) {
-        let mut guard = CURRENT_EMITTER.write();
-        if Some(ctx.address()) == *guard {
-            *guard = None;
+/// The filters set by admins, which outlast the screen's session and win over the one it logs
+/// in with.
+static FILTERS: LazyLock<Mutex<HashMap<String, Filter>>> =
+    LazyLock::new(|| Mutex::new(HashMap::default()));
+
+#[inline]
+fn seen(name: &str) {
+    SEEN.lock().insert(name.to_owned(), Instant::now());
+}
+
+#[inline]
+fn is_stale(pending: &Pending, now: Instant) -> bool {
+    now.duration_since(pending.queued) > CONFIG.emitter_queue_expiry
+}
+
+pub fn report() {
+    let payload = Emit(ByteString::from(format!(
+        r#"4{{"type":"emitter-queue","depth":{}}}"#,
+        pending()
+    )));
+    admin::notify(payload);
+}
+
+fn enqueue(item: Item, screens: Option<Vec<String>>) {
+    let now = Instant::now();
+    {
+        let mut pending = PENDING.lock();
+        pending.retain(|p| !is_stale(p, now));
+        if pending.len() >= CONFIG.emitter_queue_items {
+            tracing::warn!(target: "to-emitter", "emitter queue is full, dropping the oldest danmaku");
+            pending.pop_front();
         }
+        pending.push_back(Pending {
+            item,
+            screens,
+            queued: now,
+        });
     }
+    report();
+}
 
-    fn handle_text(&mut self, ctx: &mut DanmakuEmitterContext, text: &str) {
-        if text.trim() == EMITTER_SECRET {
-            tracing::debug!(target: "DanmakuEmitter", "emitter connected");
-            *CURRENT_EMITTER.write() = Some(ctx.address());
+fn flush(emitter: &Peer<DanmakuEmitter>, name: &str) {
+    let now = Instant::now();
+    let mut items = Vec::new();
+    {
+        let mut pending = PENDING.lock();
+        if pending.is_empty() {
+            return;
         }
+        pending.retain_mut(|p| {
+            if is_stale(p, now) {
+                return false;
+            }
+            match p.screens {
+                None => {
+                    items.push(p.item.clone());
+                    false
+                }
+                Some(ref mut screens) => {
+                    let before = screens.len();
+                    screens.retain(|screen| screen != name);
+                    if screens.len() < before {
+                        items.push(p.item.clone());
+                    }
+                    !screens.is_empty()
+                }
+            }
+        });
     }
+    if let Some(screen) = EMITTERS.read().get(emitter) {
+        let mut queue = screen.queue.lock();
+        for item in items {
+            queue.push(item, now);
+        }
+    }
+    report();
+}
 
-    fn handle_binary(&mut self, _: &mut DanmakuEmitterContext, _: Bytes) {}
+fn presence(kind: &str, name: &str) {
+    let Ok(name) = serde_json::to_string(name) else {
+        return;
+    };
+    admin::notify(Emit(ByteString::from(format!(
+        r#"4{{"type":"{kind}","name":{name}}}"#
+    ))));
+}
+
+fn send(emitter: &Peer<DanmakuEmitter>, payload: Emit) {
+    if let Err(e) = emitter.send_critical(payload) {
+        tracing::error!(target: "to-emitter", err = ?e);
+    }
+}
+
+pub fn prune() {
+    for screen in crate::libs::ws::prune(&*EMITTERS) {
+        seen(&screen.name);
+        presence("emitter-offline", &screen.name);
+    }
+}
+
+pub fn emit(payload: Emit) {
+    for emitter in EMITTERS.read().keys() {
+        send(emitter, payload.clone());
+    }
+}
+
+pub fn emit_to(screen: Option<&str>, payload: Emit) {
+    for (emitter, s) in EMITTERS.read().iter() {
+        if screen.map_or(true, |name| name == s.name) {
+            send(emitter, payload.clone());
+        }
+    }
+}
+
+fn blackout_payload(active: bool) -> Emit {
+    Emit(ByteString::from(format!(
+        r#"4{{"type":"blackout","active":{active}}}"#
+    )))
+}
+
+pub fn blackout(active: bool) {
+    if active {
+        for screen in EMITTERS.read().values() {
+            screen.queue.lock().clear();
+        }
+        PENDING.lock().clear();
+        report();
+    }
+    emit(blackout_payload(active));
 }
 
-impl Handler<Emit> for DanmakuEmitterWs {
-    type Result = ();
+pub fn route(item: Item, screens: Option<&[String]>) {
+    deliver(item, screens, false);
+}
+
+/// Like [`route`], but screens which showed the danmaku unchecked do not show it again.
+pub fn approve(item: Item, screens: Option<&[String]>) {
+    deliver(item, screens, true);
+}
+
+/// Screens that are not connected get it when they log in, unless it is stale by then; for all
+/// screens, those are the ones seen lately, or whichever comes first if none was.
+fn deliver(item: Item, screens: Option<&[String]>, approved: bool) {
+    if mode::is_blackout() {
+        return;
+    }
+    let now = Instant::now();
+    let emitters = EMITTERS.read();
+    for screen in emitters.values() {
+        if screens.map_or(true, |names| names.contains(&screen.name)) {
+            let mut queue = screen.queue.lock();
+            if approved {
+                queue.push_approved(item.clone(), now);
+            } else {
+                queue.push(item.clone(), now);
+            }
+        }
+    }
 
-    #[inline]
-    fn handle(&mut self, msg: Emit, ctx: &mut Self::Context) -> Self::Result {
-        ctx.text(msg.0);
+    match screens {
+        None => {
+            let missing: Vec<String> = {
+                let mut seen = SEEN.lock();
+                seen.retain(|_, last| now.duration_since(*last) <= CONFIG.emitter_queue_expiry);
+                seen.keys()
+                    .filter(|name| emitters.values().all(|screen| screen.name != **name))
+                    .cloned()
+                    .collect()
+            };
+            if !missing.is_empty() {
+                enqueue(item, Some(missing));
+            } else if emitters.is_empty() {
+                enqueue(item, None);
+            }
+        }
+        Some(names) => {
+            let missing: Vec<String> = names
+                .iter()
+                .filter(|name| emitters.values().all(|screen| screen.name != **name))
+                .cloned()
+                .collect();
+            if !missing.is_empty() {
+                enqueue(item, Some(missing));
+            }
+        }
+    }
+}
+
+pub fn emit_unchecked(item: &Item) {
+    if mode::is_blackout() {
+        return;
+    }
+    let now = Instant::now();
+    for screen in EMITTERS.read().values() {
+        if screen.filter == Filter::All {
+            screen.queue.lock().push_unchecked(item.clone(), now);
+        }
+    }
+}
+
+pub fn retract(id: u32, payload: Emit) {
+    for screen in EMITTERS.read().values() {
+        screen.queue.lock().remove(id);
+    }
+    PENDING.lock().retain(|p| p.item.id != Some(id));
+    emit(payload);
+}
+
+/// Runs on its own rather than in the emitter actors, which are not polled while stalled.
+pub async fn ticker() {
+    loop {
+        tokio::time::sleep(EMITTER_TICK).await;
+
+        let now = Instant::now();
+        let mut dropped = false;
+        for (emitter, screen) in EMITTERS.read().iter() {
+            let due = {
+                let mut queue = screen.queue.lock();
+                let before = queue.state().dropped;
+                let due = queue.take(now, CONFIG.emitter_rate, CONFIG.emitter_max_age);
+                dropped |= queue.state().dropped > before;
+                due
+            };
+            for item in due {
+                send(emitter, item.payload);
+            }
+        }
+        if dropped {
+            admin::notify(Emit(ByteString::from(payload())));
+        }
+    }
+}
+
+/// Also applies to a screen that is not connected, once it logs in.
+pub fn set_filter(name: &str, filter: Filter) {
+    FILTERS.lock().insert(name.to_owned(), filter);
+    for screen in EMITTERS.write().values_mut() {
+        if screen.name == name {
+            screen.filter = filter;
+        }
     }
 }
+
+pub fn screens() -> String {
+    let emitters = EMITTERS.read();
+    let screens: Vec<ScreenState> = emitters
+        .values()
+        .map(|screen| ScreenState {
+            name: &screen.name,
+            filter: screen.filter,
+            queue: screen.queue.lock().state(),
+        })
+        .collect();
+    serde_json::to_string(&screens).unwrap_or_else(|_| "[]".into())
+}
+
+#[inline]
+pub fn pending() -> usize {
+    PENDING.lock().len()
+}
+
+pub fn payload() -> String {
+    format!(r#"4{{"type":"emitters","emitters":{}}}"#, screens())
+}
+
+impl AppWsActor for DanmakuEmitter {
+    fn started(&mut self, _: &mut DanmakuEmitterContext, peer: &Peer<Self>) {
+        self.peer = Some(peer.clone());
+    }
+
+    fn stopped(&mut self, _: &mut DanmakuEmitterContext, peer: &Peer<Self>) {
+        // an evicted screen is reported when it is pruned
+        if EMITTERS.write().remove(peer).is_some()
+            && let Some(ref name) = self.name
+        {
+            tracing::debug!(target: "DanmakuEmitter", "emitter {name} disconnected");
+            seen(name);
+            presence("emitter-offline", name);
+        }
+    }
+
+    fn handle_text(&mut self, ctx: &mut DanmakuEmitterContext, text: &str) {
+        if let Some(ref name) = self.name {
+            let Ok(msg) = serde_json::from_str::<Message>(text) else {
+                ctx.text(ErrorCode::Malformed.event().0);
+                return;
+            };
+            match msg {
+                Message::Displayed { id } => {
+                    let Ok(name) = serde_json::to_string(name) else {
+                        return;
+                    };
+                    admin::notify(Emit(ByteString::from(format!(
+                        r#"4{{"type":"danmaku-displayed","id":{id},"screen":{name}}}"#
+                    ))));
+                }
+            }
+            return;
+        }
+
+        let screen = match serde_json::from_str::<Login>(text) {
+            Ok(login) if login.secret.trim() == EMITTER_SECRET => Some(Screen {
+                name: login.name,
+                filter: login.filter,
+                queue: Mutex::new(Scheduler::new(Instant::now())),
+            }),
+            Ok(_) => None,
+            Err(_) => (text.trim() == EMITTER_SECRET).then(|| Screen {
+                name: DEFAULT_SCREEN.into(),
+                filter: Filter::Approved,
+                queue: Mutex::new(Scheduler::new(Instant::now())),
+            }),
+        };
+        let (Some(mut screen), Some(peer)) = (screen, self.peer.clone()) else {
+            ctx.text(ErrorCode::Unauthorized.event().0);
+            return;
+        };
+        // the name stands for every screen in the display settings
+        if screen.name == display::ALL_SCREENS {
+            ctx.text(ErrorCode::Malformed.event().0);
+            return;
+        }
+
+        if let Some(filter) = FILTERS.lock().get(&screen.name) {
+            screen.filter = *filter;
+        }
+
+        tracing::debug!(target: "DanmakuEmitter", "emitter {} connected", screen.name);
+        if let Ok(json) = serde_json::to_string(&LoginEvent {
+            name: &screen.name,
+            filter: screen.filter,
+        }) {
+            ctx.text(format!("4{json}"));
+        }
+        ctx.text(display::payload(
+            Some(&screen.name),
+            &display::get(&screen.name),
+        ));
+        if mode::is_blackout() {
+            ctx.text(blackout_payload(true).0);
+        }
+        presence("emitter-online", &screen.name);
+        seen(&screen.name);
+        let name = screen.name.clone();
+        EMITTERS.write().insert(peer.clone(), screen);
+        flush(&peer, &name);
+        self.name = Some(name);
+    }
+
+    fn handle_binary(&mut self, _: &mut DanmakuEmitterContext, _: Bytes) {}
+}
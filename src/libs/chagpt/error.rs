@@ -0,0 +1,58 @@
+use bytestring::ByteString;
+use serde::Serialize;
+
+use super::Emit;
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCode {
+    Malformed,
+    TooLong,
+    RateLimited,
+    Unauthorized,
+    Forbidden,
+    LockedOut,
+    SessionExpired,
+    StorageFailure,
+    NotFound,
+    Banned,
+    Muted,
+    ChatClosed,
+    EmojiOnly,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "error")]
+struct ErrorEvent {
+    code: ErrorCode,
+    message: &'static str,
+}
+
+impl ErrorCode {
+    pub const fn message(self) -> &'static str {
+        match self {
+            Self::Malformed => "malformed message",
+            Self::TooLong => "content is too long",
+            Self::RateLimited => "sending too fast, wait a little",
+            Self::Unauthorized => "unauthorized",
+            Self::Forbidden => "not allowed for your role",
+            Self::LockedOut => "too many failed logins, try again later",
+            Self::SessionExpired => "session expired, log in again",
+            Self::StorageFailure => "failed to store the message",
+            Self::NotFound => "no such item",
+            Self::Banned => "you are banned",
+            Self::Muted => "you are muted",
+            Self::ChatClosed => "the chat is closed",
+            Self::EmojiOnly => "only emoji are allowed now",
+        }
+    }
+
+    pub fn event(self) -> Emit {
+        let json = serde_json::to_string(&ErrorEvent {
+            code: self,
+            message: self.message(),
+        })
+        .unwrap_or_default();
+        Emit(ByteString::from(format!("4{json}")))
+    }
+}
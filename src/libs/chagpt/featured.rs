@@ -0,0 +1,68 @@
+use std::time::SystemTime;
+
+use serde::Serialize;
+use tokio_postgres::Row;
+
+use crate::libs::{
+    db::{get_connection, BB8Error},
+    util::serialize_millis,
+};
+
+// shadowed and retracted danmakus are stored deleted and cannot be featured; featuring one
+// again keeps its first record but still counts as a hit
+const INSERT_FEATURED: &str = "insert into featured (danmaku, program, created) \
+    select id, $2, $3 from danmakus where id = $1 and not deleted \
+    on conflict (danmaku) do update set program = featured.program";
+const GET_FEATURED: &str = "select f.danmaku, d.content, d.color, f.program, f.created \
+    from featured f join danmakus d on d.id = f.danmaku \
+    where not d.deleted and ($1::int4 is null or f.program = $1) order by f.created";
+
+#[derive(Serialize)]
+pub struct Featured {
+    pub id: u32,
+    pub content: String,
+    pub color: u32,
+    pub program: Option<u32>,
+    #[serde(serialize_with = "serialize_millis")]
+    pub created: SystemTime,
+}
+
+impl Featured {
+    fn from_row(row: &Row) -> Result<Self, BB8Error> {
+        Ok(Self {
+            id: row.try_get::<_, i32>(0)? as u32,
+            content: row.try_get(1)?,
+            color: row.try_get::<_, i32>(2)? as u32,
+            program: row.try_get::<_, Option<i32>>(3)?.map(|id| id as u32),
+            created: row.try_get(4)?,
+        })
+    }
+}
+
+pub async fn feature(id: u32, program: Option<u32>) -> Result<bool, BB8Error> {
+    let mut conn = get_connection().await?;
+    let stmt = conn.prepare_static(INSERT_FEATURED.into()).await?;
+
+    let program = program.map(|id| id as i32);
+    let inserted = conn
+        .execute(&stmt, &[&(id as i32), &program, &SystemTime::now()])
+        .await?;
+    Ok(inserted > 0)
+}
+
+pub async fn list(program: Option<u32>) -> Result<Vec<Featured>, BB8Error> {
+    let mut conn = get_connection().await?;
+    let stmt = conn.prepare_static(GET_FEATURED.into()).await?;
+
+    let program = program.map(|id| id as i32);
+    conn.query(&stmt, &[&program])
+        .await?
+        .iter()
+        .map(Featured::from_row)
+        .collect()
+}
+
+pub fn payload(featured: &[Featured]) -> String {
+    let featured = serde_json::to_string(featured).unwrap_or_else(|_| "[]".into());
+    format!(r#"4{{"type":"featured","featured":{featured}}}"#)
+}
@@ -0,0 +1,48 @@
+use core::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::libs::token;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Identity(pub u64);
+
+impl Identity {
+    #[inline]
+    pub fn issue() -> Self {
+        Self(rand::random())
+    }
+
+    pub fn from_token(token: &str) -> Option<Self> {
+        let id = token::verify(token)?.strip_prefix("a:")?;
+        u64::from_str_radix(id, 16).ok().map(Self)
+    }
+
+    #[inline]
+    pub fn token(self) -> String {
+        token::sign(&format!("a:{self}"))
+    }
+}
+
+impl fmt::Display for Identity {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl Serialize for Identity {
+    #[inline]
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Identity {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        u64::from_str_radix(&hex, 16)
+            .map(Self)
+            .map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,160 @@
+use core::{future::Future, time::Duration};
+use std::{sync::LazyLock, time::Instant};
+
+use ahash::HashMap;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::types::Json;
+
+use crate::libs::{
+    constants::{CONTENT_MAX_GRAPHEMES, CONTENT_MAX_WIDTH},
+    db::{get_connection, BB8Error},
+};
+
+use super::identity::Identity;
+
+const GET_MODE: &str = "select data from chat_mode";
+const UPDATE_MODE: &str = "insert into chat_mode (id, data) values (true, $1) \
+    on conflict (id) do update set data = excluded.data";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Mode {
+    pub closed: bool,
+    /// Milliseconds between two danmakus of one identity.
+    pub slow: Option<u64>,
+    pub emoji_only: bool,
+    pub max_length: usize,
+    pub blackout: bool,
+}
+
+impl Mode {
+    const DEFAULT: Self = Self {
+        closed: false,
+        slow: None,
+        emoji_only: false,
+        max_length: CONTENT_MAX_GRAPHEMES,
+        blackout: false,
+    };
+}
+
+impl Default for Mode {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// A `slow` of zero turns slow mode off.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Update {
+    closed: Option<bool>,
+    slow: Option<u64>,
+    emoji_only: Option<bool>,
+    max_length: Option<usize>,
+    blackout: Option<bool>,
+}
+
+impl Update {
+    /// The display-width budget still applies, so longer limits would do nothing.
+    pub fn is_valid(&self) -> bool {
+        self.max_length
+            .map_or(true, |len| (1..=CONTENT_MAX_WIDTH).contains(&len))
+    }
+}
+
+static MODE: RwLock<Mode> = RwLock::new(Mode::DEFAULT);
+
+static LAST_SENT: LazyLock<Mutex<HashMap<Identity, Instant>>> =
+    LazyLock::new(|| Mutex::new(HashMap::default()));
+
+pub async fn init() -> Result<(), BB8Error> {
+    let mut conn = get_connection().await?;
+    let stmt = conn.prepare_static(GET_MODE.into()).await?;
+
+    if let Some(row) = conn.query_opt(&stmt, &[]).await? {
+        let Json(mode) = row.try_get(0)?;
+        *MODE.write() = mode;
+    }
+    Ok(())
+}
+
+async fn store(mode: &Mode) -> Result<(), BB8Error> {
+    let mut conn = get_connection().await?;
+    let stmt = conn.prepare_static(UPDATE_MODE.into()).await?;
+    conn.execute(&stmt, &[&Json(mode)]).await?;
+    Ok(())
+}
+
+#[inline]
+pub fn get() -> Mode {
+    MODE.read().clone()
+}
+
+#[inline]
+pub fn is_blackout() -> bool {
+    MODE.read().blackout
+}
+
+pub fn update(update: Update) -> (Mode, impl Future<Output = Result<(), BB8Error>>) {
+    let mut mode = MODE.write();
+    let old = mode.clone();
+    if let Some(closed) = update.closed {
+        mode.closed = closed;
+    }
+    if let Some(slow) = update.slow {
+        mode.slow = (slow > 0).then_some(slow);
+    }
+    if let Some(emoji_only) = update.emoji_only {
+        mode.emoji_only = emoji_only;
+    }
+    if let Some(max_length) = update.max_length {
+        mode.max_length = max_length;
+    }
+    if let Some(blackout) = update.blackout {
+        mode.blackout = blackout;
+    }
+    let new = mode.clone();
+    (old, async move { store(&new).await })
+}
+
+pub fn take_slot(identity: Identity, now: Instant) -> bool {
+    let Some(slow) = MODE.read().slow else {
+        return true;
+    };
+    let interval = Duration::from_millis(slow);
+
+    let mut last_sent = LAST_SENT.lock();
+    if let Some(&last) = last_sent.get(&identity)
+        && now.duration_since(last) < interval
+    {
+        return false;
+    }
+    last_sent.insert(identity, now);
+    true
+}
+
+pub fn return_slot(identity: Identity, taken: Instant) {
+    let mut last_sent = LAST_SENT.lock();
+    // the previous slot had run out already, or it could not have been taken
+    if last_sent.get(&identity) == Some(&taken) {
+        last_sent.remove(&identity);
+    }
+}
+
+pub fn prune() {
+    let slow = MODE.read().slow;
+    let mut last_sent = LAST_SENT.lock();
+    let Some(slow) = slow else {
+        last_sent.clear();
+        return;
+    };
+    let interval = Duration::from_millis(slow);
+    let now = Instant::now();
+    last_sent.retain(|_, last| now.duration_since(*last) < interval);
+}
+
+pub fn payload() -> String {
+    let mode = serde_json::to_string(&*MODE.read()).unwrap_or_else(|_| "{}".into());
+    format!(r#"4{{"type":"mode","mode":{mode}}}"#)
+}
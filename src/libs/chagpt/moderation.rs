@@ -0,0 +1,303 @@
+use core::time::Duration;
+use std::{collections::BTreeMap, time::SystemTime};
+
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize, Serializer};
+use tokio_postgres::Row;
+
+use crate::libs::{
+    constants::BAN_MAX_DURATION,
+    db::{get_connection, BB8Error},
+    util::{millis, serialize_millis},
+};
+
+use super::{
+    error::ErrorCode,
+    identity::Identity,
+    spool::{self, Entry as SpoolEntry},
+};
+
+const GET_BANS: &str = "select id, identity, ip, kind, reason, created, until from bans \
+    where not lifted and (until is null or until > now())";
+const INSERT_BAN: &str = "insert into bans (identity, ip, kind, reason, created, until) \
+    values ($1, $2, $3, $4, $5, $6) returning id";
+const LIFT_BAN: &str = "update bans set lifted = true where id = $1 and not lifted";
+const GET_HELD: &str = "select id, time from held";
+const INSERT_HELD: &str = "insert into held (id, time) values ($1, $2) on conflict (id) do nothing";
+const DELETE_HELD: &str = "delete from held where id = $1";
+
+/// A shadow ban lets the sender believe their danmakus go through while nobody else sees
+/// them, a mute stops proposals, a ban also drops the connection.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BanKind {
+    Shadow,
+    Mute,
+    Ban,
+}
+
+impl BanKind {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Shadow => "shadow",
+            Self::Mute => "mute",
+            Self::Ban => "ban",
+        }
+    }
+
+    fn from_db(kind: &str) -> Self {
+        match kind {
+            "shadow" => Self::Shadow,
+            "mute" => Self::Mute,
+            _ => Self::Ban,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Target {
+    Identity(Identity),
+    Ip(String),
+}
+
+#[inline]
+fn serialize_millis_opt<S: Serializer>(
+    time: &Option<SystemTime>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    time.map(millis).serialize(serializer)
+}
+
+#[derive(Clone, Serialize)]
+pub struct Ban {
+    pub id: u32,
+    #[serde(flatten)]
+    pub target: Target,
+    pub kind: BanKind,
+    pub reason: String,
+    #[serde(serialize_with = "serialize_millis")]
+    pub created: SystemTime,
+    #[serde(serialize_with = "serialize_millis_opt")]
+    pub until: Option<SystemTime>,
+}
+
+impl Ban {
+    #[inline]
+    fn is_active(&self, now: SystemTime) -> bool {
+        self.until.map_or(true, |until| until > now)
+    }
+
+    fn from_row(row: &Row) -> Result<Self, BB8Error> {
+        let identity: Option<i64> = row.try_get(1)?;
+        let ip: Option<String> = row.try_get(2)?;
+        // the table makes sure exactly one of them is set
+        let target = if let Some(identity) = identity {
+            Target::Identity(Identity(identity as u64))
+        } else {
+            Target::Ip(ip.unwrap_or_default())
+        };
+        Ok(Self {
+            id: row.try_get::<_, i32>(0)? as u32,
+            target,
+            kind: BanKind::from_db(row.try_get(3)?),
+            reason: row.try_get(4)?,
+            created: row.try_get(5)?,
+            until: row.try_get(6)?,
+        })
+    }
+}
+
+/// A danmaku held back for approval, or approved or deleted since.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "kebab-case")]
+pub enum Change {
+    Hold { id: u32, time: SystemTime },
+    Release { id: u32 },
+}
+
+static BANS: RwLock<Vec<Ban>> = RwLock::new(Vec::new());
+/// Held danmakus by id, with the time they were sent; they stay until an admin decides.
+static HELD: Mutex<BTreeMap<u32, SystemTime>> = Mutex::new(BTreeMap::new());
+/// Written by the danmaku flusher, in the order the changes were made.
+static CHANGES: Mutex<Vec<Change>> = Mutex::new(Vec::new());
+
+pub async fn init() -> Result<(), BB8Error> {
+    let mut conn = get_connection().await?;
+    let stmt = conn.prepare_static(GET_BANS.into()).await?;
+
+    let bans = conn
+        .query(&stmt, &[])
+        .await?
+        .iter()
+        .map(Ban::from_row)
+        .collect::<Result<_, _>>()?;
+
+    *BANS.write() = bans;
+
+    let stmt = conn.prepare_static(GET_HELD.into()).await?;
+    let held = conn
+        .query(&stmt, &[])
+        .await?
+        .iter()
+        .map(|row| Ok((row.try_get::<_, i32>(0)? as u32, row.try_get(1)?)))
+        .collect::<Result<_, BB8Error>>()?;
+
+    *HELD.lock() = held;
+    Ok(())
+}
+
+pub fn check(identity: Identity, ip: Option<&str>) -> Option<BanKind> {
+    let now = SystemTime::now();
+    BANS.read()
+        .iter()
+        .filter(|ban| ban.is_active(now))
+        .filter(|ban| match ban.target {
+            Target::Identity(id) => id == identity,
+            Target::Ip(ref addr) => ip == Some(addr.as_str()),
+        })
+        .map(|ban| ban.kind)
+        .max()
+}
+
+pub fn list() -> Vec<Ban> {
+    let now = SystemTime::now();
+    let mut guard = BANS.write();
+    guard.retain(|ban| ban.is_active(now));
+    guard.iter().rev().cloned().collect()
+}
+
+/// Empty durations are refused, and so are durations past the maximum, since the database could
+/// not store where they end.
+pub fn until(created: SystemTime, duration: Option<u64>) -> Result<Option<SystemTime>, ErrorCode> {
+    match duration {
+        Some(secs) if secs == 0 || secs > BAN_MAX_DURATION.as_secs() => Err(ErrorCode::Malformed),
+        Some(secs) => created
+            .checked_add(Duration::from_secs(secs))
+            .map(Some)
+            .ok_or(ErrorCode::Malformed),
+        None => Ok(None),
+    }
+}
+
+pub async fn add(
+    target: Target,
+    kind: BanKind,
+    reason: String,
+    created: SystemTime,
+    until: Option<SystemTime>,
+) -> Result<(), BB8Error> {
+    let mut conn = get_connection().await?;
+    let stmt = conn.prepare_static(INSERT_BAN.into()).await?;
+
+    let (identity, ip) = match target {
+        Target::Identity(identity) => (Some(identity.0 as i64), None),
+        Target::Ip(ref ip) => (None, Some(ip.as_str())),
+    };
+    let row = conn
+        .query_one(
+            &stmt,
+            &[&identity, &ip, &kind.as_str(), &reason, &created, &until],
+        )
+        .await?;
+
+    BANS.write().push(Ban {
+        id: row.try_get::<_, i32>(0)? as u32,
+        target,
+        kind,
+        reason,
+        created,
+        until,
+    });
+    Ok(())
+}
+
+pub async fn lift(id: u32) -> Result<bool, BB8Error> {
+    let mut conn = get_connection().await?;
+    let stmt = conn.prepare_static(LIFT_BAN.into()).await?;
+
+    let lifted = conn.execute(&stmt, &[&(id as i32)]).await? > 0;
+
+    BANS.write().retain(|ban| ban.id != id);
+    Ok(lifted)
+}
+
+pub fn payload() -> String {
+    let bans = serde_json::to_string(&list()).unwrap_or_else(|_| "[]".into());
+    format!(r#"4{{"type":"bans","bans":{bans}}}"#)
+}
+
+pub fn hold(id: u32, time: SystemTime) {
+    HELD.lock().insert(id, time);
+    CHANGES.lock().push(Change::Hold { id, time });
+}
+
+/// The time the danmaku was sent, if it was held back.
+pub fn release(id: u32) -> Option<SystemTime> {
+    let time = HELD.lock().remove(&id)?;
+    CHANGES.lock().push(Change::Release { id });
+    Some(time)
+}
+
+#[inline]
+pub fn held() -> usize {
+    HELD.lock().len()
+}
+
+/// Applies a change spooled by the last run, which the database does not have yet.
+pub fn restore(change: Change) {
+    let mut held = HELD.lock();
+    match change {
+        Change::Hold { id, time } => {
+            held.insert(id, time);
+        }
+        Change::Release { id } => {
+            held.remove(&id);
+        }
+    }
+}
+
+pub async fn store(change: Change) -> Result<(), BB8Error> {
+    let mut conn = get_connection().await?;
+    match change {
+        Change::Hold { id, time } => {
+            let stmt = conn.prepare_static(INSERT_HELD.into()).await?;
+            conn.execute(&stmt, &[&(id as i32), &time]).await?;
+        }
+        Change::Release { id } => {
+            let stmt = conn.prepare_static(DELETE_HELD.into()).await?;
+            conn.execute(&stmt, &[&(id as i32)]).await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn flush() {
+    let changes = core::mem::take(&mut *CHANGES.lock());
+    if changes.is_empty() {
+        return;
+    }
+
+    let mut written = 0;
+    if spool::backlog() == 0 {
+        for &change in &changes {
+            if let Err(e) = store(change).await {
+                tracing::warn!(target: "ChaGPT-moderation", "failed to store held danmakus, spooling them: {e:?}");
+                break;
+            }
+            written += 1;
+        }
+    }
+    if written == changes.len() {
+        return;
+    }
+
+    let entries: Vec<SpoolEntry> = changes
+        .into_iter()
+        .skip(written)
+        .map(SpoolEntry::Moderation)
+        .collect();
+    if let Err(e) = spool::append(&entries).await {
+        tracing::error!(target: "ChaGPT-moderation", "failed to spool {} held danmaku changes: {e:?}", entries.len());
+    }
+}
@@ -4,18 +4,32 @@ use tokio_postgres::types::Json;
 
 use crate::libs::db::{get_connection, BB8Error};
 
+use super::spool::{self, Entry};
+
 const GET_REPERTOIRE: &str = "select data from repertoire";
 const UPDATE_REPERTOIRE: &str = "insert into repertoire (data) values ($1) on conflict ((1)) do update set data = excluded.data";
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DanmakuPolicy {
+    #[default]
+    Open,
+    Moderated,
+    Closed,
+    AutoForward,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Program {
     pub id: u32,
     pub name: String,
     pub performer: String,
     pub time: String,
+    #[serde(default)]
+    pub policy: DanmakuPolicy,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Repertoire {
     pub programs: Vec<Program>,
     pub current: u32,
@@ -23,6 +37,17 @@ pub struct Repertoire {
 
 pub static REPERTOIRE: RwLock<Option<Repertoire>> = RwLock::new(None);
 
+pub fn policy() -> DanmakuPolicy {
+    let guard = REPERTOIRE.read();
+    let Some(ref r) = *guard else {
+        return DanmakuPolicy::default();
+    };
+    r.programs
+        .iter()
+        .find(|program| program.id == r.current)
+        .map_or_else(DanmakuPolicy::default, |program| program.policy)
+}
+
 pub async fn init() -> Result<(), BB8Error> {
     let mut conn = get_connection().await?;
     let stmt = conn.prepare_static(GET_REPERTOIRE.into()).await?;
@@ -34,20 +59,78 @@ pub async fn init() -> Result<(), BB8Error> {
     Ok(())
 }
 
-pub async fn update(data: Repertoire) -> Result<String, BB8Error> {
+pub async fn store(data: &Repertoire) -> Result<(), BB8Error> {
     let mut conn = get_connection().await?;
     let stmt = conn.prepare_static(UPDATE_REPERTOIRE.into()).await?;
 
-    let data = Json(data);
-    conn.execute(&stmt, &[&data]).await?;
+    conn.execute(&stmt, &[&Json(data)]).await?;
+    Ok(())
+}
+
+pub async fn update(data: Repertoire) -> String {
+    // a spooled snapshot must not overwrite a newer one later, so queue behind it
+    let spooled = if spool::backlog() > 0 {
+        true
+    } else if let Err(e) = store(&data).await {
+        tracing::warn!(target: "ChaGPT-admin", "failed to store repertoire, spooling it: {e:?}");
+        true
+    } else {
+        false
+    };
+    if spooled {
+        if let Err(e) = spool::append(&[Entry::Repertoire(data.clone())]).await {
+            tracing::error!(target: "ChaGPT-admin", "failed to spool repertoire: {e:?}");
+        }
+    }
 
     let payload = format!(
         r#"4{{"type":"repertoire","programs":{},"current":{}}}"#,
-        serde_json::to_string(&data.0.programs).unwrap_or_else(|_| "[]".into()),
-        data.0.current
+        serde_json::to_string(&data.programs).unwrap_or_else(|_| "[]".into()),
+        data.current
     );
 
-    *REPERTOIRE.write() = Some(data.0);
+    let old = policy();
+    *REPERTOIRE.write() = Some(data);
+    let new = policy();
+    if new != old {
+        tracing::info!(target: "ChaGPT-admin", "danmaku policy {old:?} => {new:?}");
+    }
+
+    payload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{policy, DanmakuPolicy, Program, Repertoire, REPERTOIRE};
+    use crate::libs::logger;
+
+    #[test]
+    fn current_policy() {
+        logger::init();
+
+        assert_eq!(policy(), DanmakuPolicy::Open);
+
+        let program = |id, policy| Program {
+            id,
+            name: format!("program {id}"),
+            performer: String::new(),
+            time: String::new(),
+            policy,
+        };
+        *REPERTOIRE.write() = Some(Repertoire {
+            programs: vec![
+                program(1, DanmakuPolicy::Moderated),
+                program(2, DanmakuPolicy::Closed),
+            ],
+            current: 2,
+        });
+        assert_eq!(policy(), DanmakuPolicy::Closed);
+
+        REPERTOIRE.write().as_mut().unwrap().current = 1;
+        assert_eq!(policy(), DanmakuPolicy::Moderated);
 
-    Ok(payload)
+        // a program missing from the list falls back to the default
+        REPERTOIRE.write().as_mut().unwrap().current = 3;
+        assert_eq!(policy(), DanmakuPolicy::Open);
+    }
 }
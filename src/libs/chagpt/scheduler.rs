@@ -0,0 +1,192 @@
+use core::time::Duration;
+use std::{collections::VecDeque, time::Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::libs::constants::EMITTER_UNCHECKED_MEMORY;
+
+use super::Emit;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Priority {
+    #[default]
+    Normal,
+    High,
+}
+
+#[derive(Clone)]
+pub struct Item {
+    pub id: Option<u32>,
+    pub payload: Emit,
+    pub priority: Priority,
+}
+
+#[derive(Clone, Copy, Serialize)]
+pub struct State {
+    pub high: usize,
+    pub normal: usize,
+    pub dropped: u64,
+}
+
+pub struct Scheduler {
+    high: VecDeque<(Item, Instant)>,
+    normal: VecDeque<(Item, Instant)>,
+    tokens: f64,
+    last: Instant,
+    dropped: u64,
+    /// Ids pushed before they were approved, most recent last.
+    unchecked: VecDeque<u32>,
+}
+
+impl Scheduler {
+    pub const fn new(now: Instant) -> Self {
+        Self {
+            high: VecDeque::new(),
+            normal: VecDeque::new(),
+            tokens: 1.0,
+            last: now,
+            dropped: 0,
+            unchecked: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, item: Item, now: Instant) {
+        match item.priority {
+            Priority::High => self.high.push_back((item, now)),
+            Priority::Normal => self.normal.push_back((item, now)),
+        }
+    }
+
+    pub fn push_unchecked(&mut self, item: Item, now: Instant) {
+        if let Some(id) = item.id {
+            if self.unchecked.len() >= EMITTER_UNCHECKED_MEMORY {
+                self.unchecked.pop_front();
+            }
+            self.unchecked.push_back(id);
+        }
+        self.push(item, now);
+    }
+
+    /// Skips an item the screen was already given unchecked.
+    pub fn push_approved(&mut self, item: Item, now: Instant) {
+        if item.id.is_some_and(|id| self.unchecked.contains(&id)) {
+            return;
+        }
+        self.push(item, now);
+    }
+
+    pub fn clear(&mut self) {
+        self.high.clear();
+        self.normal.clear();
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        self.high.retain(|(item, _)| item.id != Some(id));
+        self.normal.retain(|(item, _)| item.id != Some(id));
+    }
+
+    /// Normal items older than `max_age` are dropped instead. A rate of zero sends everything at
+    /// once.
+    pub fn take(&mut self, now: Instant, rate: f64, max_age: Duration) -> Vec<Item> {
+        let before = self.normal.len();
+        self.normal
+            .retain(|(_, queued)| now.duration_since(*queued) <= max_age);
+        self.dropped += (before - self.normal.len()) as u64;
+
+        if rate <= 0.0 {
+            self.last = now;
+            return self
+                .high
+                .drain(..)
+                .chain(self.normal.drain(..))
+                .map(|(item, _)| item)
+                .collect();
+        }
+
+        // a second worth of tokens at most, so an idle screen does not get a burst later
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate.max(1.0));
+        self.last = now;
+
+        let mut due = Vec::new();
+        while self.tokens >= 1.0 {
+            let Some((item, _)) = self.high.pop_front().or_else(|| self.normal.pop_front()) else {
+                break;
+            };
+            self.tokens -= 1.0;
+            due.push(item);
+        }
+        due
+    }
+
+    pub fn state(&self) -> State {
+        State {
+            high: self.high.len(),
+            normal: self.normal.len(),
+            dropped: self.dropped,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+    use std::time::Instant;
+
+    use bytestring::ByteString;
+
+    use super::{Item, Priority, Scheduler};
+    use crate::libs::{chagpt::Emit, logger};
+
+    fn item(id: u32, priority: Priority) -> Item {
+        Item {
+            id: Some(id),
+            payload: Emit(ByteString::from(format!("4{id}"))),
+            priority,
+        }
+    }
+
+    fn ids(items: &[Item]) -> Vec<u32> {
+        items.iter().filter_map(|item| item.id).collect()
+    }
+
+    #[test]
+    fn scheduler() {
+        logger::init();
+
+        let max_age = Duration::from_secs(15);
+        let t0 = Instant::now();
+        let mut scheduler = Scheduler::new(t0);
+        scheduler.push(item(1, Priority::Normal), t0);
+        scheduler.push(item(2, Priority::Normal), t0);
+        scheduler.push(item(3, Priority::High), t0);
+        scheduler.push(item(4, Priority::Normal), t0);
+        scheduler.remove(4);
+
+        assert_eq!(ids(&scheduler.take(t0, 2.0, max_age)), [3]);
+        let t1 = t0 + Duration::from_secs(1);
+        assert_eq!(ids(&scheduler.take(t1, 2.0, max_age)), [1, 2]);
+
+        scheduler.push(item(5, Priority::Normal), t1);
+        scheduler.push(item(6, Priority::High), t1);
+        let t2 = t1 + Duration::from_secs(20);
+        assert_eq!(ids(&scheduler.take(t2, 2.0, max_age)), [6]);
+        assert_eq!(scheduler.state().dropped, 1);
+    }
+
+    #[test]
+    fn approve_after_open() {
+        logger::init();
+
+        let max_age = Duration::from_secs(15);
+        let t0 = Instant::now();
+        let mut scheduler = Scheduler::new(t0);
+        scheduler.push_unchecked(item(1, Priority::Normal), t0);
+        assert_eq!(ids(&scheduler.take(t0, 0.0, max_age)), [1]);
+
+        scheduler.push_approved(item(1, Priority::Normal), t0);
+        scheduler.push_approved(item(2, Priority::Normal), t0);
+        assert_eq!(ids(&scheduler.take(t0, 0.0, max_age)), [2]);
+    }
+}
@@ -0,0 +1,341 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    time::Instant,
+};
+
+use bytestring::ByteString;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use bb8_postgres::bb8::RunError;
+
+use crate::libs::{
+    config::CONFIG,
+    constants::{DANMAKU_BATCH_SIZE, SPOOL_RETRY_INTERVAL},
+    db::BB8Error,
+};
+
+use super::{
+    admin,
+    audit::{self, Record},
+    batcher::{self, Row},
+    moderation::{self, Change},
+    repertoire::{self, Repertoire},
+    Emit,
+};
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Entry {
+    Danmaku(Row),
+    Repertoire(Repertoire),
+    Retract { id: u32 },
+    Audit(Record),
+    Moderation(Change),
+}
+
+static BACKLOG: AtomicUsize = AtomicUsize::new(0);
+static FILE: Mutex<()> = Mutex::new(());
+/// The highest id end persisted, so that a slower write cannot move the mark back.
+static MARK: Mutex<u32> = Mutex::new(0);
+/// A replay that failed is not retried before then, as each one reads the whole file.
+static RETRY_AT: Mutex<Option<Instant>> = Mutex::new(None);
+
+#[inline]
+pub fn backlog() -> usize {
+    BACKLOG.load(Ordering::SeqCst)
+}
+
+pub fn report() {
+    let payload = Emit(ByteString::from(format!(
+        r#"4{{"type":"spool","backlog":{}}}"#,
+        backlog()
+    )));
+    admin::notify(payload);
+}
+
+/// Runs file I/O off the runtime threads, since every write waits for the disk.
+async fn blocking<T, F>(f: F) -> io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Unparsable lines (e.g. torn by a crash) are kept as `None` so that line counts stay exact.
+fn read() -> io::Result<Vec<Option<Entry>>> {
+    let text = match fs::read_to_string(&CONFIG.spool_path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(text
+        .lines()
+        .map(|line| {
+            let entry = serde_json::from_str(line);
+            if let Err(ref e) = entry {
+                tracing::error!(target: "spool", "skipping corrupted entry: {e:?}");
+            }
+            entry.ok()
+        })
+        .collect())
+}
+
+fn mark_path() -> String {
+    format!("{}.ids", CONFIG.spool_path)
+}
+
+fn dead_letter_path() -> String {
+    format!("{}.dead", CONFIG.spool_path)
+}
+
+pub async fn mark(end: u32) -> io::Result<()> {
+    blocking(move || {
+        let mut mark = MARK.lock();
+        if end <= *mark {
+            return Ok(());
+        }
+        let tmp = format!("{}.tmp", mark_path());
+        let mut file = File::create(&tmp)?;
+        write!(file, "{end}")?;
+        file.sync_data()?;
+        fs::rename(&tmp, mark_path())?;
+        *mark = end;
+        Ok(())
+    })
+    .await
+}
+
+pub async fn init() -> Option<u32> {
+    blocking(|| Ok(load())).await.unwrap_or_else(|e| {
+        tracing::error!(target: "spool", "failed to read spool: {e:?}");
+        None
+    })
+}
+
+fn load() -> Option<u32> {
+    let _guard = FILE.lock();
+    let entries = match read() {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::error!(target: "spool", "failed to read spool: {e:?}");
+            Vec::new()
+        }
+    };
+    BACKLOG.store(entries.len(), Ordering::SeqCst);
+    if !entries.is_empty() {
+        tracing::warn!(target: "spool", "{} entries left from the last run", entries.len());
+    }
+    let mut spooled = None;
+    for entry in entries.iter().flatten() {
+        match *entry {
+            Entry::Danmaku(ref row) => spooled = spooled.max(Some(row.id + 1)),
+            Entry::Moderation(change) => moderation::restore(change),
+            _ => (),
+        }
+    }
+    let mark = fs::read_to_string(mark_path())
+        .ok()
+        .and_then(|text| text.trim().parse().ok());
+    if let Some(mark) = mark {
+        *MARK.lock() = mark;
+    }
+    spooled.max(mark)
+}
+
+pub async fn dead_letter(entry: &Entry) -> io::Result<()> {
+    let mut buf = serde_json::to_vec(entry)?;
+    buf.push(b'\n');
+
+    blocking(move || {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dead_letter_path())?;
+        file.write_all(&buf)?;
+        file.sync_data()
+    })
+    .await
+}
+
+fn is_rejected(e: &BB8Error) -> bool {
+    let RunError::User(e) = e else {
+        return false;
+    };
+    // data exceptions and integrity constraint violations
+    e.code()
+        .is_some_and(|code| code.code().starts_with("22") || code.code().starts_with("23"))
+}
+
+async fn reject(entry: &Entry, e: &BB8Error) {
+    tracing::error!(target: "spool", "database rejected an entry, moving it to the dead letters: {e:?}");
+    if let Err(e) = dead_letter(entry).await {
+        tracing::error!(target: "spool", "failed to keep a dead letter: {e:?}");
+    }
+}
+
+/// `lines` holds the spool lines each row accounts for, counted into `done` as soon as the row
+/// is written, so that a fallback stopping halfway does not replay them again.
+async fn write_batch(batch: &[Row], lines: &[usize], done: &mut usize) -> Result<(), BB8Error> {
+    match batcher::write(batch).await {
+        Err(e) if is_rejected(&e) => {
+            for (row, lines) in batch.iter().zip(lines) {
+                match batcher::write(core::slice::from_ref(row)).await {
+                    Err(e) if is_rejected(&e) => reject(&Entry::Danmaku(row.clone()), &e).await,
+                    res => res?,
+                }
+                *done += lines;
+            }
+            Ok(())
+        }
+        res => {
+            res?;
+            *done += lines.iter().sum::<usize>();
+            Ok(())
+        }
+    }
+}
+
+async fn write_entry(entry: &Entry) -> Result<(), BB8Error> {
+    let res = match *entry {
+        Entry::Danmaku(ref row) => batcher::write(core::slice::from_ref(row)).await,
+        Entry::Repertoire(ref data) => repertoire::store(data).await,
+        Entry::Retract { id } => batcher::write_retraction(id).await.map(|_| ()),
+        Entry::Audit(ref record) => audit::insert(record).await,
+        Entry::Moderation(change) => moderation::store(change).await,
+    };
+    match res {
+        Err(e) if is_rejected(&e) => {
+            reject(entry, &e).await;
+            Ok(())
+        }
+        res => res,
+    }
+}
+
+pub async fn append(entries: &[Entry]) -> io::Result<()> {
+    let mut buf = Vec::new();
+    for entry in entries {
+        serde_json::to_writer(&mut buf, entry)?;
+        buf.push(b'\n');
+    }
+
+    let count = entries.len();
+    blocking(move || {
+        let _guard = FILE.lock();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&CONFIG.spool_path)?;
+        file.write_all(&buf)?;
+        file.sync_data()?;
+        BACKLOG.fetch_add(count, Ordering::SeqCst);
+        Ok(())
+    })
+    .await?;
+
+    report();
+    Ok(())
+}
+
+/// Entries appended meanwhile are kept after the rest.
+fn truncate(done: usize) -> io::Result<()> {
+    let _guard = FILE.lock();
+    let text = fs::read_to_string(&CONFIG.spool_path)?;
+    let rest: Vec<&str> = text.lines().skip(done).collect();
+
+    let tmp = format!("{}.tmp", CONFIG.spool_path);
+    let mut file = File::create(&tmp)?;
+    for line in &rest {
+        writeln!(file, "{line}")?;
+    }
+    file.sync_data()?;
+    fs::rename(&tmp, &CONFIG.spool_path)?;
+
+    BACKLOG.store(rest.len(), Ordering::SeqCst);
+    Ok(())
+}
+
+async fn replay_entries(entries: Vec<Option<Entry>>, done: &mut usize) -> Result<(), BB8Error> {
+    let mut batch = Vec::new();
+    // lines covered by each row of `batch`, including the corrupted ones before it which are
+    // simply dropped
+    let mut lines = Vec::new();
+    let mut corrupted = 0;
+    for entry in entries {
+        match entry {
+            Some(Entry::Danmaku(row)) => {
+                batch.push(row);
+                lines.push(corrupted + 1);
+                corrupted = 0;
+                if batch.len() == DANMAKU_BATCH_SIZE {
+                    write_batch(&batch, &lines, done).await?;
+                    batch.clear();
+                    lines.clear();
+                }
+            }
+            None => corrupted += 1,
+            Some(entry) => {
+                if !batch.is_empty() {
+                    write_batch(&batch, &lines, done).await?;
+                    batch.clear();
+                    lines.clear();
+                }
+                *done += corrupted;
+                corrupted = 0;
+                write_entry(&entry).await?;
+                *done += 1;
+            }
+        }
+    }
+    if !batch.is_empty() {
+        write_batch(&batch, &lines, done).await?;
+    }
+    *done += corrupted;
+    Ok(())
+}
+
+/// Waits a while after a failure, and tells the caller to keep spooling meanwhile.
+pub async fn replay() -> bool {
+    if RETRY_AT.lock().is_some_and(|at| Instant::now() < at) {
+        return false;
+    }
+    let entries = match blocking(|| {
+        let _guard = FILE.lock();
+        read()
+    })
+    .await
+    {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::error!(target: "spool", "failed to read spool: {e:?}");
+            *RETRY_AT.lock() = Some(Instant::now() + SPOOL_RETRY_INTERVAL);
+            return false;
+        }
+    };
+    let total = entries.len();
+
+    let mut done = 0;
+    if let Err(e) = replay_entries(entries, &mut done).await {
+        tracing::warn!(target: "spool", "replay stopped after {done}/{total} entries: {e:?}");
+        *RETRY_AT.lock() = Some(Instant::now() + SPOOL_RETRY_INTERVAL);
+    } else {
+        tracing::info!(target: "spool", "replayed {total} entries");
+        *RETRY_AT.lock() = None;
+    }
+
+    if done > 0 {
+        if let Err(e) = blocking(move || truncate(done)).await {
+            tracing::error!(target: "spool", "failed to truncate spool: {e:?}");
+            *RETRY_AT.lock() = Some(Instant::now() + SPOOL_RETRY_INTERVAL);
+            return false;
+        }
+        report();
+    }
+    backlog() == 0
+}
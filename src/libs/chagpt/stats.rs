@@ -0,0 +1,60 @@
+use std::{collections::VecDeque, time::Instant};
+
+use bytestring::ByteString;
+use parking_lot::Mutex;
+
+use crate::libs::{
+    constants::{ADMIN_STATS_INTERVAL, STATS_WINDOW},
+    eth,
+};
+
+use super::{
+    admin::{self, ADMINS},
+    chagpt::ACTORS,
+    emitter, moderation, Emit,
+};
+
+static RECENT: Mutex<VecDeque<Instant>> = Mutex::new(VecDeque::new());
+
+fn expire_recent(recent: &mut VecDeque<Instant>, now: Instant) {
+    while recent
+        .front()
+        .is_some_and(|time| now.duration_since(*time) > STATS_WINDOW)
+    {
+        recent.pop_front();
+    }
+}
+
+pub fn accepted(now: Instant) {
+    let mut recent = RECENT.lock();
+    expire_recent(&mut recent, now);
+    recent.push_back(now);
+}
+
+fn danmaku_per_minute(now: Instant) -> usize {
+    let mut recent = RECENT.lock();
+    expire_recent(&mut recent, now);
+    recent.len()
+}
+
+pub fn payload() -> String {
+    format!(
+        r#"4{{"type":"stats","online":{},"danmakuPerMinute":{},"pendingModeration":{},"emitters":{},"emitterQueue":{},"ethBlocks":{}}}"#,
+        ACTORS.read().len(),
+        danmaku_per_minute(Instant::now()),
+        moderation::held(),
+        emitter::screens(),
+        emitter::pending(),
+        eth::available(),
+    )
+}
+
+pub async fn reporter() {
+    loop {
+        tokio::time::sleep(ADMIN_STATS_INTERVAL).await;
+
+        if !ADMINS.read().is_empty() {
+            admin::notify(Emit(ByteString::from(payload())));
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use core::{str::FromStr, time::Duration};
+use std::sync::LazyLock;
+
+use super::{
+    constants::{
+        CLIENT_IP_HEADER, EMITTER_MAX_AGE, EMITTER_QUEUE_EXPIRY, EMITTER_QUEUE_MAX_ITEMS,
+        EMITTER_RATE, OUTBOX_DEADLINE, OUTBOX_MAX_BYTES, OUTBOX_MAX_MESSAGES, SPOOL_PATH,
+        TRUSTED_PROXIES,
+    },
+    request::Cidr,
+};
+
+pub struct Config {
+    pub outbox_messages: usize,
+    pub outbox_bytes: usize,
+    pub outbox_deadline: Duration,
+    pub spool_path: String,
+    pub emitter_queue_items: usize,
+    pub emitter_queue_expiry: Duration,
+    /// Zero turns pacing off.
+    pub emitter_rate: f64,
+    pub emitter_max_age: Duration,
+    pub trusted_proxies: Vec<Cidr>,
+    /// `forwarded` or a list of addresses like `x-forwarded-for`; whatever else the client sends is
+    /// ignored.
+    pub client_ip_header: String,
+    pub ip_salt: Option<String>,
+    pub metrics_token: Option<String>,
+}
+
+pub static CONFIG: LazyLock<Config> = LazyLock::new(Config::from_env);
+
+fn env<T: FromStr>(key: &str, default: T) -> T {
+    let Ok(value) = std::env::var(key) else {
+        return default;
+    };
+    if let Ok(value) = value.trim().parse() {
+        value
+    } else {
+        tracing::warn!(target: "config", "invalid value {value:?} of {key}, using the default");
+        default
+    }
+}
+
+#[inline]
+fn env_duration(key: &str, default: Duration) -> Duration {
+    Duration::from_millis(env(key, default.as_millis() as u64))
+}
+
+fn env_list<T: FromStr>(key: &str, default: &str) -> Vec<T> {
+    let value = std::env::var(key).unwrap_or_else(|_| default.to_owned());
+    value
+        .split(',')
+        .filter(|item| !item.trim().is_empty())
+        .filter_map(|item| {
+            let parsed = item.trim().parse().ok();
+            if parsed.is_none() {
+                tracing::warn!(target: "config", "ignoring invalid item {item:?} of {key}");
+            }
+            parsed
+        })
+        .collect()
+}
+
+impl Config {
+    fn from_env() -> Self {
+        Self {
+            outbox_messages: env("CHAGPT_OUTBOX_MESSAGES", OUTBOX_MAX_MESSAGES),
+            outbox_bytes: env("CHAGPT_OUTBOX_BYTES", OUTBOX_MAX_BYTES),
+            outbox_deadline: env_duration("CHAGPT_OUTBOX_DEADLINE_MS", OUTBOX_DEADLINE),
+            spool_path: env("CHAGPT_SPOOL_PATH", SPOOL_PATH.to_owned()),
+            emitter_queue_items: env("CHAGPT_EMITTER_QUEUE_ITEMS", EMITTER_QUEUE_MAX_ITEMS),
+            emitter_queue_expiry: env_duration(
+                "CHAGPT_EMITTER_QUEUE_EXPIRY_MS",
+                EMITTER_QUEUE_EXPIRY,
+            ),
+            emitter_rate: env("CHAGPT_EMITTER_RATE", EMITTER_RATE),
+            emitter_max_age: env_duration("CHAGPT_EMITTER_MAX_AGE_MS", EMITTER_MAX_AGE),
+            trusted_proxies: env_list("CHAGPT_TRUSTED_PROXIES", TRUSTED_PROXIES),
+            client_ip_header: env("CHAGPT_CLIENT_IP_HEADER", CLIENT_IP_HEADER.to_owned())
+                .to_ascii_lowercase(),
+            ip_salt: std::env::var("CHAGPT_IP_SALT").ok().filter(|salt| !salt.is_empty()),
+            metrics_token: std::env::var("CHAGPT_METRICS_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty()),
+        }
+    }
+}
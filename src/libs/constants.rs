@@ -11,6 +11,25 @@ pub const BYTES_FALSE: Bytes = Bytes::from_static(b"false");
 
 pub const DB_CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
 
+pub const DANMAKU_ID_BLOCK: u32 = 64;
+pub const DANMAKU_BATCH_SIZE: usize = 256;
+pub const DANMAKU_FLUSH_INTERVAL: Duration = Duration::from_millis(200);
+pub const SPOOL_PATH: &str = "chagpt.spool";
+pub const SPOOL_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+pub const NONCE_WINDOW: Duration = Duration::from_secs(300);
+pub const NONCE_MAX_LENGTH: usize = 64;
+pub const NONCE_MAX_ENTRIES: usize = 100_000;
+pub const ONLINE_INTERVAL: Duration = Duration::from_secs(3);
+pub const LOGIN_MAX_FAILURES: u32 = 5;
+pub const LOGIN_FAILURE_WINDOW: Duration = Duration::from_secs(600);
+pub const LOGIN_LOCKOUT: Duration = Duration::from_secs(900);
+pub const AUDIT_PAGE_SIZE: u32 = 100;
+pub const ADMIN_SESSION_TTL: Duration = Duration::from_secs(12 * 3600);
+
+pub const CONTENT_MAX_GRAPHEMES: usize = 128;
+pub const CONTENT_MAX_WIDTH: usize = 192;
+pub const CONTENT_MAX_GRAPHEME_CHARS: usize = 16;
+
 pub const ETH_URL: &str = "https://www.blockchain.com/explorer/blocks/eth";
 pub const ETH_TIMEOUT: Duration = Duration::from_secs(10);
 pub const ETH_INTERNAL: Duration = Duration::from_secs(15);
@@ -18,6 +37,27 @@ pub const ETH_INTERNAL: Duration = Duration::from_secs(15);
 pub const PING_INTERVAL: Duration = Duration::from_millis(18320);
 pub const PING_TIMEOUT: Duration = Duration::from_millis(28560);
 
-pub const ADMIN_SECRET: &str = include_str!("../../admin.secret");
+pub const TRUSTED_PROXIES: &str = "127.0.0.0/8,::1";
+/// The reverse proxy in front of the backend puts the client address in this header.
+pub const CLIENT_IP_HEADER: &str = "009f34034b761c32384fde345378c488efc18c59";
+
+pub const OUTBOX_MAX_MESSAGES: usize = 256;
+pub const OUTBOX_MAX_BYTES: usize = 0x4_0000;
+pub const OUTBOX_DEADLINE: Duration = Duration::from_secs(10);
+pub const OUTBOX_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+pub const EMITTER_QUEUE_MAX_ITEMS: usize = 64;
+pub const EMITTER_QUEUE_EXPIRY: Duration = Duration::from_secs(60);
+pub const EMITTER_RATE: f64 = 4.0;
+pub const EMITTER_MAX_AGE: Duration = Duration::from_secs(15);
+pub const EMITTER_TICK: Duration = Duration::from_millis(100);
+pub const EMITTER_UNCHECKED_MEMORY: usize = 4096;
+pub const FEATURED_DURATION: Duration = Duration::from_secs(8);
+
+pub const ADMIN_STATS_INTERVAL: Duration = Duration::from_secs(5);
+pub const STATS_WINDOW: Duration = Duration::from_secs(60);
+pub const BAN_MAX_DURATION: Duration = Duration::from_secs(100 * 365 * 86400);
+
 pub const EMITTER_SECRET: &str = include_str!("../../emitter.secret");
 pub const LOTTERY_SECRET: &str = include_str!("../../lottery.secret");
+pub const TOKEN_SECRET: &str = include_str!("../../token.secret");
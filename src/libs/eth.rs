@@ -1,9 +1,11 @@
+use core::time::Duration;
 use std::{
     collections::BTreeMap,
     sync::{
         atomic::{AtomicBool, Ordering},
         LazyLock,
     },
+    time::SystemTime,
 };
 
 use ahash::HashSet;
@@ -12,7 +14,10 @@ use reqwest::Client;
 use scraper::{Html, Selector};
 use serde::Deserialize;
 
-use super::constants::{ETH_INTERNAL, ETH_TIMEOUT, ETH_URL};
+use super::{
+    constants::{ETH_INTERNAL, ETH_TIMEOUT, ETH_URL},
+    util::millis,
+};
 
 #[cfg_attr(debug_assertions, derive(Debug))]
 #[derive(Clone, Deserialize)]
@@ -44,6 +49,29 @@ pub fn fetch() -> Option<Block> {
     None
 }
 
+pub fn available() -> usize {
+    let blocks = BLOCKS.read();
+    let ban = BAN.read();
+    blocks.keys().filter(|height| !ban.contains(*height)).count()
+}
+
+pub fn draw() -> Option<String> {
+    let block = fetch()?;
+
+    let bt = SystemTime::UNIX_EPOCH + Duration::from_secs(block.time);
+    let nt = SystemTime::now();
+    let now = millis(nt);
+
+    tracing::info!(target: "eth-request", "Block {} (blockTime: {bt:?}, now: {nt:?}) is taken", block.height);
+
+    Some(format!(
+        r#"{{"type":"lottery","block":{},"hash":"{}","blockTime":{},"now":{now}}}"#,
+        block.height,
+        block.hash,
+        block.time * 1000,
+    ))
+}
+
 pub async fn fetcher() {
     #[derive(Debug, Deserialize)]
     struct EthRespPageBlocks<'a> {
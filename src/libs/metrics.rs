@@ -0,0 +1,24 @@
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+pub static DANMAKU_FLUSH_FAILURES: AtomicU64 = AtomicU64::new(0);
+pub static WS_DROPPED: AtomicU64 = AtomicU64::new(0);
+pub static WS_EVICTED: AtomicU64 = AtomicU64::new(0);
+
+static COUNTERS: &[(&str, &str, &AtomicU64)] = &[
+    ("chagpt_danmaku_flush_failures_total", "Failed danmaku batch flushes", &DANMAKU_FLUSH_FAILURES),
+    ("chagpt_ws_dropped_total", "Non-critical events dropped from stalled sessions", &WS_DROPPED),
+    ("chagpt_ws_evicted_total", "Sessions evicted for staying over the outbox limit", &WS_EVICTED),
+];
+
+pub fn render() -> String {
+    let mut out = String::new();
+    for (name, help, counter) in COUNTERS {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} counter");
+        let _ = writeln!(out, "{name} {}", counter.load(Ordering::Relaxed));
+    }
+    out
+}
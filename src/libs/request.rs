@@ -1,5 +1,14 @@
-use actix_http::Method;
-use actix_web::guard::{Guard, GuardContext};
+use core::{fmt::Write, str::FromStr};
+use std::net::IpAddr;
+
+use actix_http::{header::HeaderMap, Method};
+use actix_web::{
+    guard::{Guard, GuardContext},
+    HttpRequest,
+};
+use sha2::{Digest, Sha256};
+
+use super::config::CONFIG;
 
 #[allow(non_camel_case_types)]
 pub struct POST_or_HEAD;
@@ -11,3 +20,196 @@ impl Guard for POST_or_HEAD {
         method == Method::POST || method == Method::OPTIONS
     }
 }
+
+#[derive(Clone, Copy, Debug)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix)).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix)).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| ())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| ())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(());
+        }
+        Ok(Self { addr, prefix })
+    }
+}
+
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim().trim_matches('"');
+    if let Ok(ip) = hop.parse() {
+        return Some(ip);
+    }
+    if let Some(rest) = hop.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    hop.rsplit_once(':')?.0.parse().ok()
+}
+
+/// A hop which cannot be parsed ends the walk, as nothing before it can be trusted either.
+fn walk<'a>(hops: impl DoubleEndedIterator<Item = &'a str>, trusted: &[Cidr]) -> Option<IpAddr> {
+    let mut last = None;
+    for hop in hops.rev() {
+        let ip = parse_hop(hop)?;
+        if !trusted.iter().any(|cidr| cidr.contains(ip)) {
+            return Some(ip);
+        }
+        last = Some(ip);
+    }
+    last
+}
+
+/// A peer over the Unix socket (`None`) is always the local reverse proxy. Only `header` is
+/// read, as the proxy passes the other forwarding headers on from the client.
+pub fn resolve(
+    peer: Option<IpAddr>,
+    headers: &HeaderMap,
+    header: &str,
+    trusted: &[Cidr],
+) -> Option<IpAddr> {
+    if let Some(peer) = peer
+        && !trusted.iter().any(|cidr| cidr.contains(peer))
+    {
+        return Some(peer);
+    }
+
+    let values: Vec<&str> = headers
+        .get_all(header)
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    if values.is_empty() {
+        return peer;
+    }
+    let value = values.join(",");
+
+    if header == "forwarded" {
+        let hops = value.split(',').filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim().eq_ignore_ascii_case("for").then_some(value)
+            })
+        });
+        let hops: Vec<&str> = hops.collect();
+        return walk(hops.into_iter(), trusted).or(peer);
+    }
+    walk(value.split(','), trusted).or(peer)
+}
+
+#[inline]
+pub fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    resolve(
+        req.peer_addr().map(|addr| addr.ip()),
+        req.headers(),
+        &CONFIG.client_ip_header,
+        &CONFIG.trusted_proxies,
+    )
+}
+
+#[inline]
+pub fn client_ip_key(req: &HttpRequest) -> Option<String> {
+    client_ip(req).map(ip_key)
+}
+
+/// The address itself, unless a salt is configured to keep it out of storage and logs.
+pub fn ip_key(ip: IpAddr) -> String {
+    let Some(ref salt) = CONFIG.ip_salt else {
+        return ip.to_string();
+    };
+
+    let digest = Sha256::new()
+        .chain_update(salt.as_bytes())
+        .chain_update(ip.to_string().as_bytes())
+        .finalize();
+    let mut key = String::with_capacity(2 + 32);
+    key.push_str("h:");
+    for b in &digest[..16] {
+        let _ = write!(key, "{b:02x}");
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_http::header::{HeaderMap, HeaderName, HeaderValue};
+
+    use super::{resolve, Cidr};
+    use crate::libs::logger;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+        map
+    }
+
+    #[test]
+    fn client_ip() {
+        logger::init();
+
+        let trusted: Vec<Cidr> = ["10.0.0.0/8", "::1"].iter().map(|s| s.parse().unwrap()).collect();
+        let proxy = Some("10.1.2.3".parse().unwrap());
+        let ip = |s: &str| Some(s.parse().unwrap());
+
+        assert!(trusted[0].contains("::ffff:10.9.9.9".parse().unwrap()));
+        assert!(!trusted[0].contains("11.0.0.1".parse().unwrap()));
+
+        // untrusted peers cannot forge headers
+        let h = headers(&[("x-forwarded-for", "1.1.1.1")]);
+        assert_eq!(resolve(ip("8.8.8.8"), &h, "x-forwarded-for", &trusted), ip("8.8.8.8"));
+
+        let h = headers(&[("x-forwarded-for", "6.6.6.6, 1.1.1.1, 10.0.0.2")]);
+        assert_eq!(resolve(proxy, &h, "x-forwarded-for", &trusted), ip("1.1.1.1"));
+        assert_eq!(resolve(None, &h, "x-forwarded-for", &trusted), ip("1.1.1.1"));
+
+        let h = headers(&[("forwarded", r#"for=6.6.6.6, for="[2001:db8::17]:4711";proto=https"#)]);
+        assert_eq!(resolve(proxy, &h, "forwarded", &trusted), ip("2001:db8::17"));
+
+        let h = headers(&[("x-real-ip", "1.2.3.4")]);
+        assert_eq!(resolve(proxy, &h, "x-real-ip", &trusted), ip("1.2.3.4"));
+
+        // headers other than the configured one come straight from the client
+        let h = headers(&[("forwarded", "for=9.9.9.9"), ("x-real-ip", "1.2.3.4")]);
+        assert_eq!(resolve(proxy, &h, "x-real-ip", &trusted), ip("1.2.3.4"));
+        let h = headers(&[("forwarded", "for=9.9.9.9"), ("x-forwarded-for", "9.9.9.9")]);
+        assert_eq!(resolve(proxy, &h, "x-real-ip", &trusted), proxy);
+
+        let h = headers(&[("x-forwarded-for", "1.1.1.1, unknown")]);
+        assert_eq!(resolve(proxy, &h, "x-forwarded-for", &trusted), proxy);
+
+        assert_eq!(resolve(None, &HeaderMap::new(), "x-real-ip", &trusted), None);
+    }
+}
@@ -0,0 +1,72 @@
+use core::fmt::Write;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::constants::TOKEN_SECRET;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[inline]
+fn mac(payload: &str) -> HmacSha256 {
+    // HMAC accepts keys of any length
+    let mut mac = unsafe { HmacSha256::new_from_slice(TOKEN_SECRET.as_bytes()).unwrap_unchecked() };
+    mac.update(payload.as_bytes());
+    mac
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+pub fn sign(payload: &str) -> String {
+    let tag = mac(payload).finalize().into_bytes();
+
+    let mut token = String::with_capacity(payload.len() + 1 + tag.len() * 2);
+    token.push_str(payload);
+    token.push('.');
+    for b in tag {
+        let _ = write!(token, "{b:02x}");
+    }
+    token
+}
+
+pub fn verify(token: &str) -> Option<&str> {
+    let (payload, tag) = token.rsplit_once('.')?;
+    mac(payload).verify_slice(&decode_hex(tag)?).ok()?;
+    Some(payload)
+}
+
+/// Compares tags in constant time, so that neither the contents nor the lengths leak.
+pub fn matches(given: &str, expected: &str) -> bool {
+    mac(given)
+        .verify_slice(&mac(expected).finalize().into_bytes())
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{matches, sign, verify};
+    use crate::libs::logger;
+
+    #[test]
+    fn token() {
+        logger::init();
+
+        let token = sign("a:0123456789abcdef");
+        assert_eq!(verify(&token), Some("a:0123456789abcdef"));
+        assert_eq!(verify(&token.replacen("a:0", "a:1", 1)), None);
+        assert_eq!(verify(&token[..token.len() - 1]), None);
+        assert_eq!(verify("a:0123456789abcdef"), None);
+
+        assert!(matches("metrics", "metrics"));
+        assert!(!matches("metric", "metrics"));
+        assert!(!matches("", "metrics"));
+    }
+}
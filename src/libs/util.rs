@@ -1,3 +1,7 @@
+use std::time::SystemTime;
+
+use serde::Serializer;
+
 #[macro_export]
 macro_rules! assume {
     ($cond:expr) => {
@@ -10,3 +14,17 @@ macro_rules! assume {
         }
     };
 }
+
+#[inline]
+pub fn millis(time: SystemTime) -> u128 {
+    unsafe {
+        time.duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_unchecked()
+            .as_millis()
+    }
+}
+
+#[inline]
+pub fn serialize_millis<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u128(millis(*time))
+}
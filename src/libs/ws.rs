@@ -1,15 +1,31 @@
-use core::str::Utf8Chunks;
+use core::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash, Hasher},
+    str::Utf8Chunks,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Instant,
+};
 
-use actix::{Actor, ActorContext, Addr, AsyncContext, SpawnHandle, StreamHandler};
+use actix::{Actor, ActorContext, Addr, AsyncContext, Handler, SpawnHandle, StreamHandler};
 use actix_http::ws::Item;
 use actix_web::web::Bytes;
 use actix_web_actors::ws;
+use bytestring::ByteString;
+use parking_lot::{Mutex, RwLock};
 
-use crate::libs::constants::{PING_INTERVAL, PING_TIMEOUT};
+use crate::libs::{
+    config::CONFIG,
+    constants::{PING_INTERVAL, PING_TIMEOUT},
+    metrics,
+};
 
 pub trait AppWsActor: Sized + Unpin + 'static {
-    fn started(&mut self, ctx: &mut ws::WebsocketContext<WsActor<Self>>, hash: u64);
-    fn stopped(&mut self, ctx: &mut ws::WebsocketContext<WsActor<Self>>, hash: u64);
+    fn started(&mut self, ctx: &mut ws::WebsocketContext<WsActor<Self>>, peer: &Peer<Self>);
+    fn stopped(&mut self, ctx: &mut ws::WebsocketContext<WsActor<Self>>, peer: &Peer<Self>);
     fn handle_text(&mut self, ctx: &mut ws::WebsocketContext<WsActor<Self>>, text: &str);
     fn handle_binary(&mut self, ctx: &mut ws::WebsocketContext<WsActor<Self>>, bin: Bytes);
 }
@@ -19,6 +35,7 @@ where
     A: AppWsActor,
 {
     pub app: A,
+    outbox: Arc<Outbox>,
     tmp_buffer: Vec<u8>,
     is_tmp_buffer_string: bool,
     with_engine_io: bool,
@@ -30,9 +47,10 @@ impl<A> WsActor<A>
 where
     A: AppWsActor,
 {
-    pub const fn new(app: A, withEngineIO: bool) -> Self {
+    pub fn new(app: A, withEngineIO: bool, policy: Policy) -> Self {
         Self {
             app,
+            outbox: Arc::new(Outbox::new(policy)),
             tmp_buffer: Vec::new(),
             is_tmp_buffer_string: false,
             with_engine_io: withEngineIO,
@@ -41,6 +59,14 @@ where
         }
     }
 
+    #[inline]
+    fn peer(&self, ctx: &ws::WebsocketContext<Self>) -> Peer<A> {
+        Peer {
+            addr: ctx.address(),
+            outbox: self.outbox.clone(),
+        }
+    }
+
     fn refresh_ping(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
         fn ping_scheduler<A: AppWsActor>(
             _actor: &mut WsActor<A>,
@@ -78,22 +104,21 @@ where
 
     #[inline]
     fn started(&mut self, ctx: &mut Self::Context) {
-        let addr = ctx.address();
-        let hash = hack::get(&addr);
+        let peer = self.peer(ctx);
         if self.with_engine_io {
             ctx.text(r#"0{"pingInterval":18320,"pingTimeout":10240,"upgrades":[]}"#);
-            self.app.started(ctx, hash);
+            self.app.started(ctx, &peer);
             self.refresh_ping(ctx);
             self.refresh_timeout(ctx);
         } else {
-            self.app.started(ctx, hash);
+            self.app.started(ctx, &peer);
         }
     }
 
     #[inline]
     fn stopped(&mut self, ctx: &mut Self::Context) {
-        let addr = ctx.address();
-        self.app.stopped(ctx, hack::get(&addr));
+        let peer = self.peer(ctx);
+        self.app.stopped(ctx, &peer);
     }
 }
 
@@ -103,6 +128,10 @@ where
 {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
         const LENGTH_LIMIT: usize = 0x10_0000;
+        if self.outbox.is_evicted() {
+            ctx.stop();
+            return;
+        }
         if self.with_engine_io {
             self.refresh_timeout(ctx);
         }
@@ -170,6 +199,254 @@ where
     }
 }
 
+#[derive(Clone)]
+#[repr(transparent)]
+pub struct Emit(pub ByteString);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// Drop the oldest non-critical events; evict only if critical events alone stay over the
+    /// limit past the deadline.
+    DropOldest,
+    Disconnect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    Closed,
+    Evicted,
+}
+
+#[derive(Default)]
+struct OutboxQueue {
+    items: VecDeque<(ByteString, bool)>,
+    bytes: usize,
+    over_since: Option<Instant>,
+}
+
+impl OutboxQueue {
+    #[inline]
+    fn is_over(&self) -> bool {
+        self.items.len() > CONFIG.outbox_messages || self.bytes > CONFIG.outbox_bytes
+    }
+
+    fn drop_oldest(&mut self) {
+        let mut i = 0;
+        while self.is_over() && i < self.items.len() {
+            if self.items[i].1 {
+                i += 1;
+            } else if let Some((text, _)) = self.items.remove(i) {
+                self.bytes -= text.len();
+                metrics::WS_DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// `Addr::do_send` does not respect the mailbox capacity, and a stalled socket stops the actor
+/// from being polled at all, so the limits have to be enforced on the sending side.
+struct Outbox {
+    queue: Mutex<OutboxQueue>,
+    evicted: AtomicBool,
+    policy: Policy,
+}
+
+impl Outbox {
+    fn new(policy: Policy) -> Self {
+        Self {
+            queue: Mutex::new(OutboxQueue::default()),
+            evicted: AtomicBool::new(false),
+            policy,
+        }
+    }
+
+    #[inline]
+    fn is_evicted(&self) -> bool {
+        self.evicted.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the queue was empty, i.e. whether the actor has to be woken up.
+    fn push(&self, text: ByteString, critical: bool) -> Result<bool, SendError> {
+        if self.is_evicted() {
+            return Err(SendError::Evicted);
+        }
+
+        let mut queue = self.queue.lock();
+        let was_empty = queue.items.is_empty();
+        queue.bytes += text.len();
+        queue.items.push_back((text, critical));
+
+        if queue.is_over() && self.policy == Policy::DropOldest {
+            queue.drop_oldest();
+        }
+        if !queue.is_over() {
+            queue.over_since = None;
+            return Ok(was_empty);
+        }
+
+        let now = Instant::now();
+        queue.over_since.get_or_insert(now);
+        if self.expire(&mut queue, now) {
+            return Err(SendError::Evicted);
+        }
+        Ok(was_empty)
+    }
+
+    fn expire(&self, queue: &mut OutboxQueue, now: Instant) -> bool {
+        let Some(since) = queue.over_since else {
+            return false;
+        };
+        if now.duration_since(since) < CONFIG.outbox_deadline {
+            return false;
+        }
+
+        self.evicted.store(true, Ordering::Relaxed);
+        *queue = OutboxQueue::default();
+        metrics::WS_EVICTED.fetch_add(1, Ordering::Relaxed);
+        tracing::warn!(target: "ws-outbox", "evicted a session stalled for {:?}", now - since);
+        true
+    }
+
+    /// Also checks the deadline, for sessions which are not sent anything that would.
+    fn is_alive(&self, now: Instant) -> bool {
+        !self.is_evicted() && !self.expire(&mut self.queue.lock(), now)
+    }
+
+    #[inline]
+    fn close(&self) {
+        self.evicted.store(true, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn take(&self) -> VecDeque<(ByteString, bool)> {
+        let mut queue = self.queue.lock();
+        queue.bytes = 0;
+        queue.over_since = None;
+        core::mem::take(&mut queue.items)
+    }
+}
+
+pub struct Peer<A>
+where
+    A: AppWsActor,
+{
+    addr: Addr<WsActor<A>>,
+    outbox: Arc<Outbox>,
+}
+
+impl<A> Peer<A>
+where
+    A: AppWsActor,
+{
+    #[inline]
+    pub fn addr_hash(&self) -> u64 {
+        hack::get(&self.addr)
+    }
+
+    #[inline]
+    pub fn is_evicted(&self) -> bool {
+        self.outbox.is_evicted()
+    }
+
+    #[inline]
+    pub fn send(&self, msg: Emit) -> Result<(), SendError> {
+        self.push(msg, false)
+    }
+
+    #[inline]
+    pub fn send_critical(&self, msg: Emit) -> Result<(), SendError> {
+        self.push(msg, true)
+    }
+
+    fn push(&self, msg: Emit, critical: bool) -> Result<(), SendError> {
+        if self.outbox.push(msg.0, critical)? {
+            self.addr.do_send(Flush).map_err(|_| SendError::Closed)?;
+        }
+        Ok(())
+    }
+
+    pub fn close(&self) {
+        self.outbox.close();
+        let _ = self.addr.do_send(Flush);
+    }
+}
+
+impl<A: AppWsActor> Clone for Peer<A> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            addr: self.addr.clone(),
+            outbox: self.outbox.clone(),
+        }
+    }
+}
+
+impl<A: AppWsActor> PartialEq for Peer<A> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.addr == other.addr
+    }
+}
+
+impl<A: AppWsActor> Eq for Peer<A> {}
+
+impl<A: AppWsActor> Hash for Peer<A> {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.addr.hash(state);
+    }
+}
+
+impl<A: AppWsActor> Borrow<Addr<WsActor<A>>> for Peer<A> {
+    #[inline]
+    fn borrow(&self) -> &Addr<WsActor<A>> {
+        &self.addr
+    }
+}
+
+/// A stalled session is not polled, so it cannot take itself out. Returns the values of the
+/// sessions removed.
+pub fn prune<A, V, S>(registry: &RwLock<HashMap<Peer<A>, V, S>>) -> Vec<V>
+where
+    A: AppWsActor,
+    S: BuildHasher,
+{
+    let now = Instant::now();
+    let evicted: Vec<Peer<A>> = registry
+        .read()
+        .keys()
+        .filter(|peer| !peer.outbox.is_alive(now))
+        .cloned()
+        .collect();
+    if evicted.is_empty() {
+        return Vec::new();
+    }
+    let mut guard = registry.write();
+    evicted.iter().filter_map(|peer| guard.remove(peer)).collect()
+}
+
+struct Flush;
+
+impl actix::Message for Flush {
+    type Result = ();
+}
+
+impl<A> Handler<Flush> for WsActor<A>
+where
+    A: AppWsActor,
+{
+    type Result = ();
+
+    fn handle(&mut self, _: Flush, ctx: &mut Self::Context) -> Self::Result {
+        for (text, _) in self.outbox.take() {
+            ctx.text(text);
+        }
+        if self.outbox.is_evicted() {
+            ctx.stop();
+        }
+    }
+}
+
 pub mod hack {
     use super::{Actor, Addr};
 
@@ -203,3 +480,79 @@ pub mod hack {
         h.finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use bytestring::ByteString;
+
+    use super::{Outbox, Policy, SendError};
+    use crate::libs::{config::CONFIG, logger};
+
+    fn text(i: usize) -> ByteString {
+        ByteString::from(format!("4{i}"))
+    }
+
+    fn texts(outbox: &Outbox) -> Vec<ByteString> {
+        outbox.take().into_iter().map(|(text, _)| text).collect()
+    }
+
+    #[test]
+    fn drop_oldest() {
+        logger::init();
+
+        let limit = CONFIG.outbox_messages;
+        let outbox = Outbox::new(Policy::DropOldest);
+        assert_eq!(outbox.push(text(0), true), Ok(true));
+        for i in 1..=limit {
+            assert_eq!(outbox.push(text(i), false), Ok(false));
+        }
+        // the critical one stays, the oldest of the others goes
+        let sent = texts(&outbox);
+        assert_eq!(sent.len(), limit);
+        assert_eq!(sent[0], text(0));
+        assert_eq!(sent[1], text(2));
+        assert_eq!(outbox.push(text(0), false), Ok(true));
+    }
+
+    #[test]
+    fn critical_overflow() {
+        logger::init();
+
+        let limit = CONFIG.outbox_messages;
+        let outbox = Outbox::new(Policy::DropOldest);
+        for i in 0..=limit {
+            assert!(outbox.push(text(i), true).is_ok());
+        }
+        // nothing left to drop but the new one
+        assert!(outbox.push(text(limit + 1), false).is_ok());
+        assert_eq!(outbox.queue.lock().items.len(), limit + 1);
+
+        assert!(outbox.is_alive(Instant::now()));
+        assert!(!outbox.is_alive(Instant::now() + CONFIG.outbox_deadline));
+        assert_eq!(outbox.push(text(0), true), Err(SendError::Evicted));
+        assert!(texts(&outbox).is_empty());
+    }
+
+    #[test]
+    fn disconnect() {
+        logger::init();
+
+        let limit = CONFIG.outbox_messages;
+        let outbox = Outbox::new(Policy::Disconnect);
+        for i in 0..=limit {
+            assert!(outbox.push(text(i), false).is_ok());
+        }
+        assert!(outbox.is_alive(Instant::now() + CONFIG.outbox_deadline / 2));
+        // draining the queue in time stops the clock
+        assert_eq!(texts(&outbox).len(), limit + 1);
+        assert!(outbox.is_alive(Instant::now() + CONFIG.outbox_deadline));
+
+        for i in 0..=limit {
+            assert!(outbox.push(text(i), false).is_ok());
+        }
+        assert!(!outbox.is_alive(Instant::now() + CONFIG.outbox_deadline));
+        assert!(outbox.is_evicted());
+    }
+}
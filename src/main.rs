@@ -76,6 +76,11 @@ async fn main() -> std::io::Result<()> {
     libs::chagpt::init().await;
 
     tokio::task::spawn(libs::eth::fetcher());
+    tokio::task::spawn(libs::chagpt::batcher::flusher());
+    tokio::task::spawn(libs::chagpt::stats::reporter());
+    tokio::task::spawn(libs::chagpt::chagpt::online_reporter());
+    tokio::task::spawn(libs::chagpt::sweeper());
+    tokio::task::spawn(libs::chagpt::emitter::ticker());
 
     let json_config = web::JsonConfig::default()
         .content_type(|_| true)
@@ -93,12 +98,26 @@ async fn main() -> std::io::Result<()> {
             .wrap(middleware::NormalizePath::new(
                 middleware::TrailingSlash::MergeOnly,
             ))
-            .wrap(middleware::Logger::new(
-                r#"%{009f34034b761c32384fde345378c488efc18c59}i %a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T"#
-            ))
+            .wrap(
+                middleware::Logger::new(
+                    r#"%{client_ip}xi %{peer_ip}xi "%{request_line}xi" %s %b "%{Referer}i" "%{User-Agent}i" %T"#
+                )
+                .custom_request_replace("client_ip", |req| {
+                    libs::request::client_ip_key(req.request()).unwrap_or_else(|| "-".to_owned())
+                })
+                .custom_request_replace("peer_ip", |req| {
+                    req.peer_addr()
+                        .map_or_else(|| "-".to_owned(), |addr| libs::request::ip_key(addr.ip()))
+                })
+                // without the query string, which carries the audience's identity token
+                .custom_request_replace("request_line", |req| {
+                    format!("{} {} {:?}", req.method(), req.path(), req.version())
+                }),
+            )
             .service(api::chagpt::chagpt)
             .service(api::chagpt::chagpt_admin)
             .service(api::chagpt::emitter)
+            .service(api::metrics::metrics)
             .service(
                 web::resource("/block")
                     .guard(libs::request::POST_or_HEAD)